@@ -0,0 +1,110 @@
+//! Demangling of native symbols, i.e. Itanium C++ ABI and Rust (legacy and v0) symbols.
+//! Should not contain any wasm dependencies.
+
+use cpp_demangle::{DemangleOptions, Symbol};
+
+/// Demangle the symbol.
+/// Returns None if the symbol isn't mangled or can't be demangled.
+pub fn demangle(symbol: &str) -> Option<String> {
+    // legacy Rust symbols are valid Itanium symbols as well, so Rust is tried first
+    if let Ok(demangled) = rustc_demangle::try_demangle(symbol) {
+        // alternate format omits the hash
+        return Some(format!("{:#}", demangled));
+    }
+
+    // Mach-O symbols have an extra leading underscore
+    let symbol = if symbol.starts_with("__Z") {
+        &symbol[1..]
+    } else {
+        symbol
+    };
+    if !symbol.starts_with("_Z") {
+        return None;
+    }
+    Symbol::new(symbol)
+        .ok()
+        .and_then(|s| s.demangle(&DemangleOptions::default()).ok())
+}
+
+/// Demangle the symbols contained in the text, which are separated by whitespaces
+/// and may be followed by an offset like `+0x1c`.
+/// Returns None if no symbol is demangled.
+pub fn demangle_symbols(text: &str) -> Option<String> {
+    let mut result = String::with_capacity(text.len());
+    let mut demangled_any = false;
+    let mut rest = text;
+    while !rest.is_empty() {
+        let token_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let (token, after) = rest.split_at(token_end);
+        let (symbol, offset) = match token.rfind("+0x") {
+            Some(i) => token.split_at(i),
+            None => (token, ""),
+        };
+        match demangle(symbol) {
+            Some(demangled) => {
+                demangled_any = true;
+                result.push_str(demangled.as_str());
+                result.push_str(offset);
+            }
+            None => result.push_str(token),
+        }
+
+        let whitespace_end = after
+            .find(|c: char| !c.is_whitespace())
+            .unwrap_or(after.len());
+        result.push_str(&after[..whitespace_end]);
+        rest = &after[whitespace_end..];
+    }
+
+    if demangled_any {
+        Some(result)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::demangle::{demangle, demangle_symbols};
+
+    #[test]
+    fn test_demangle() {
+        assert_eq!(
+            demangle("_ZN2os13PlatformEvent4parkEl").unwrap(),
+            "os::PlatformEvent::park(long)"
+        );
+        assert_eq!(
+            demangle("__ZN6Thread8call_runEv").unwrap(),
+            "Thread::call_run()"
+        );
+        // legacy Rust
+        assert_eq!(
+            demangle("_ZN4core3fmt5write17h0123456789abcdefE").unwrap(),
+            "core::fmt::write"
+        );
+        // Rust v0
+        assert_eq!(
+            demangle("_RNvCs1234_7mycrate4jnif").unwrap(),
+            "mycrate::jnif"
+        );
+
+        assert_eq!(demangle("pthread_cond_timedwait"), None);
+        assert_eq!(demangle("java/lang/Thread.run"), None);
+        assert_eq!(demangle("_Zinvalid"), None);
+    }
+
+    #[test]
+    fn test_demangle_symbols() {
+        assert_eq!(
+            demangle_symbols(
+                "  14: [0xffff8d57e498] _ZN2os13PlatformEvent4parkEl+0xd0 [libjvm.so]"
+            )
+            .unwrap(),
+            "  14: [0xffff8d57e498] os::PlatformEvent::park(long)+0xd0 [libjvm.so]"
+        );
+        assert_eq!(
+            demangle_symbols("  13: [0xffff8dadcc10] pthread_cond_timedwait+0x23c [libc.so.6]"),
+            None
+        );
+    }
+}
@@ -0,0 +1,209 @@
+//! Decoding of compressed or archived input files.
+//! Should not contain any wasm dependencies.
+
+use anyhow::{anyhow, Result};
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use std::io::{Cursor, Read, Seek};
+#[cfg(target_arch = "wasm32")]
+use tsify::Tsify;
+use zip::ZipArchive;
+
+/// Encoding of the input file
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[cfg_attr(target_arch = "wasm32", derive(Tsify))]
+#[cfg_attr(target_arch = "wasm32", tsify(into_wasm_abi, from_wasm_abi))]
+pub enum Encoding {
+    Uncompressed,
+    Gzip,
+    Zstd,
+    Lz4,
+    Zip,
+    /// Detect the encoding from the magic bytes
+    Auto,
+}
+
+impl Encoding {
+    const MAGIC_GZIP: &'static [u8] = &[0x1f, 0x8b];
+    const MAGIC_ZSTD: &'static [u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+    const MAGIC_LZ4: &'static [u8] = &[0x04, 0x22, 0x4d, 0x18];
+    const MAGIC_ZIP: &'static [u8] = &[0x50, 0x4b, 0x03, 0x04];
+    const MAGIC_ZIP_EMPTY: &'static [u8] = &[0x50, 0x4b, 0x05, 0x06];
+
+    /// Detect the encoding from the magic bytes.
+    /// Falls back to Uncompressed if no known magic is found.
+    pub fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(Self::MAGIC_GZIP) {
+            Self::Gzip
+        } else if bytes.starts_with(Self::MAGIC_ZSTD) {
+            Self::Zstd
+        } else if bytes.starts_with(Self::MAGIC_LZ4) {
+            Self::Lz4
+        } else if bytes.starts_with(Self::MAGIC_ZIP) || bytes.starts_with(Self::MAGIC_ZIP_EMPTY) {
+            Self::Zip
+        } else {
+            Self::Uncompressed
+        }
+    }
+}
+
+/// Decode the bytes into the raw file content.
+///
+/// For zip archives, `entry_name` selects the file to extract.
+/// It can be omitted only when the archive contains exactly one file.
+/// The extracted entry is decoded once more with auto-detection, so e.g. `.jfr.gz` in a zip works.
+/// Archives nested in the archive are rejected.
+pub fn decode(bytes: Vec<u8>, encoding: Encoding, entry_name: Option<&str>) -> Result<Vec<u8>> {
+    let encoding = match encoding {
+        Encoding::Auto => Encoding::detect(&bytes),
+        e => e,
+    };
+
+    let mut decoded = Vec::new();
+    match encoding {
+        Encoding::Uncompressed | Encoding::Auto => return Ok(bytes),
+        Encoding::Gzip => {
+            GzDecoder::new(Cursor::new(bytes)).read_to_end(&mut decoded)?;
+        }
+        Encoding::Zstd => {
+            ruzstd::StreamingDecoder::new(Cursor::new(bytes))
+                .map_err(|e| anyhow!("Failed to decode zstd: {}", e))?
+                .read_to_end(&mut decoded)?;
+        }
+        Encoding::Lz4 => {
+            lz4_flex::frame::FrameDecoder::new(Cursor::new(bytes)).read_to_end(&mut decoded)?;
+        }
+        Encoding::Zip => {
+            let mut archive = ZipArchive::new(Cursor::new(bytes))?;
+            let name = match entry_name {
+                Some(name) => name.to_string(),
+                None => {
+                    let mut entries = file_entries(&mut archive);
+                    if entries.len() != 1 {
+                        return Err(anyhow!(
+                            "Archive contains {} files. Select one of them",
+                            entries.len()
+                        ));
+                    }
+                    entries.remove(0)
+                }
+            };
+            archive.by_name(name.as_str())?.read_to_end(&mut decoded)?;
+            let encoding = Encoding::detect(&decoded);
+            if encoding == Encoding::Zip {
+                return Err(anyhow!("Nested archives are not supported"));
+            }
+            return decode(decoded, encoding, None);
+        }
+    }
+    Ok(decoded)
+}
+
+/// List the names of the files in the zip archive
+pub fn archive_entries(bytes: &[u8]) -> Result<Vec<String>> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes))?;
+    Ok(file_entries(&mut archive))
+}
+
+/// Names of the files (excluding directories) in the order of the archive
+fn file_entries<R: Read + Seek>(archive: &mut ZipArchive<R>) -> Vec<String> {
+    let mut names = vec![];
+    for i in 0..archive.len() {
+        if let Ok(file) = archive.by_index_raw(i) {
+            if !file.is_dir() {
+                names.push(file.name().to_string());
+            }
+        }
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encoding::{archive_entries, decode, Encoding};
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::{Cursor, Write};
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    const CONTENT: &[u8] = b"FLR\0dummy recording";
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Encoding::detect(CONTENT), Encoding::Uncompressed);
+        assert_eq!(Encoding::detect(&gzip(CONTENT)), Encoding::Gzip);
+        assert_eq!(Encoding::detect(&[0x28, 0xb5, 0x2f, 0xfd]), Encoding::Zstd);
+        assert_eq!(Encoding::detect(&[0x04, 0x22, 0x4d, 0x18]), Encoding::Lz4);
+        assert_eq!(Encoding::detect(b"PK\x03\x04"), Encoding::Zip);
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(
+            decode(gzip(CONTENT), Encoding::Auto, None).unwrap(),
+            CONTENT
+        );
+
+        // zstd frame consisting of single raw block
+        let mut zstd = vec![0x28, 0xb5, 0x2f, 0xfd, 0x20, CONTENT.len() as u8];
+        let block_header = ((CONTENT.len() as u32) << 3) | 1;
+        zstd.extend_from_slice(&block_header.to_le_bytes()[..3]);
+        zstd.extend_from_slice(CONTENT);
+        assert_eq!(decode(zstd, Encoding::Auto, None).unwrap(), CONTENT);
+
+        let mut encoder = lz4_flex::frame::FrameEncoder::new(vec![]);
+        encoder.write_all(CONTENT).unwrap();
+        let lz4 = encoder.finish().unwrap();
+        assert_eq!(decode(lz4, Encoding::Auto, None).unwrap(), CONTENT);
+    }
+
+    #[test]
+    fn test_decode_zip() {
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        writer
+            .add_directory("dir/", FileOptions::default())
+            .unwrap();
+        writer
+            .start_file("dir/a.jfr.gz", FileOptions::default())
+            .unwrap();
+        writer.write_all(&gzip(CONTENT)).unwrap();
+        writer.start_file("b.jfr", FileOptions::default()).unwrap();
+        writer.write_all(b"FLR\0another").unwrap();
+        let zip = writer.finish().unwrap().into_inner();
+
+        assert_eq!(
+            archive_entries(&zip).unwrap(),
+            vec!["dir/a.jfr.gz".to_string(), "b.jfr".to_string()]
+        );
+        assert!(decode(zip.clone(), Encoding::Auto, None).is_err());
+        assert_eq!(
+            decode(zip, Encoding::Zip, Some("dir/a.jfr.gz")).unwrap(),
+            CONTENT
+        );
+    }
+
+    #[test]
+    fn test_decode_nested_zip() {
+        let mut inner = ZipWriter::new(Cursor::new(vec![]));
+        inner.start_file("a.jfr", FileOptions::default()).unwrap();
+        inner.write_all(CONTENT).unwrap();
+        let inner = inner.finish().unwrap().into_inner();
+
+        let mut outer = ZipWriter::new(Cursor::new(vec![]));
+        outer.start_file("a.zip", FileOptions::default()).unwrap();
+        outer.write_all(&inner).unwrap();
+        let outer = outer.finish().unwrap().into_inner();
+
+        assert_eq!(
+            decode(outer, Encoding::Auto, None).unwrap_err().to_string(),
+            "Nested archives are not supported"
+        );
+    }
+}
@@ -0,0 +1,276 @@
+//! Grouping of threads into timeline rows by the normalized thread name,
+//! so that e.g. hundreds of thread pool workers can be collapsed into a single row.
+//! Should not contain any wasm dependencies.
+
+use crate::execution_sample::Profile;
+use crate::profile::{SampleEventType, Thread, ThreadState};
+use anyhow::Result;
+use regex::Regex;
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde::{Deserialize, Serialize};
+#[cfg(target_arch = "wasm32")]
+use tsify::Tsify;
+
+/// How to group threads into rows
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[cfg_attr(target_arch = "wasm32", derive(Tsify))]
+#[cfg_attr(target_arch = "wasm32", tsify(into_wasm_abi, from_wasm_abi))]
+#[serde(rename_all = "camelCase")]
+pub struct ThreadGroupConfig {
+    pub enabled: bool,
+    /// Regex to extract the group name from the thread name.
+    /// The first capture group (or the whole match if there's no group) is used as the group name,
+    /// and threads which don't match are not grouped.
+    /// If not specified, the thread name with digits stripped is used.
+    pub pattern: Option<String>,
+}
+
+/// A row of the timeline
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Row {
+    /// Aggregate of the threads which have the same group name
+    Group {
+        name: String,
+        /// Indices of the member threads in the filtered threads
+        thread_indices: Vec<usize>,
+        expanded: bool,
+    },
+    /// Index of the thread in the filtered threads
+    Thread(usize),
+}
+
+/// Sample counts of each state in a time bucket
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct StateCounts {
+    /// Indexed by the ThreadState
+    states: [u32; ThreadState::ALL.len()],
+    /// Samples from jdk.NativeMethodSample regardless of the thread state
+    pub native_method: u32,
+    /// Samples not passing the filter
+    pub hidden: u32,
+}
+
+impl StateCounts {
+    pub fn state(&self, state: ThreadState) -> u32 {
+        self.states[state as usize]
+    }
+
+    pub fn total(&self) -> u32 {
+        self.states.iter().sum::<u32>() + self.native_method + self.hidden
+    }
+}
+
+/// Normalize the thread name into the group name.
+/// Returns None if the thread shouldn't be grouped.
+pub fn group_name(name: &str, pattern: Option<&Regex>) -> Option<String> {
+    match pattern {
+        Some(regex) => regex.captures(name).map(|c| {
+            c.get(1)
+                .or_else(|| c.get(0))
+                .map(|m| m.as_str().to_string())
+                .unwrap_or_default()
+        }),
+        None => Some(name.chars().filter(|c| !c.is_ascii_digit()).collect()),
+    }
+}
+
+/// Arrange the threads into rows.
+/// Groups are placed at the position of their first member, and only groups which have
+/// more than one member are made into group rows.
+pub fn rows(
+    threads: &[Thread],
+    config: &ThreadGroupConfig,
+    expanded_groups: &FxHashSet<String>,
+) -> Result<Vec<Row>> {
+    if !config.enabled {
+        return Ok((0..threads.len()).map(Row::Thread).collect());
+    }
+    let pattern = match &config.pattern {
+        Some(pattern) => Some(Regex::new(pattern.as_str())?),
+        None => None,
+    };
+
+    let names: Vec<Option<String>> = threads
+        .iter()
+        .map(|t| group_name(t.name.as_str(), pattern.as_ref()))
+        .collect();
+    let mut members: FxHashMap<&str, Vec<usize>> = FxHashMap::default();
+    for (i, name) in names.iter().enumerate() {
+        if let Some(name) = name {
+            members.entry(name.as_str()).or_default().push(i);
+        }
+    }
+
+    let mut rows = vec![];
+    let mut emitted = vec![false; threads.len()];
+    for (i, name) in names.iter().enumerate() {
+        if emitted[i] {
+            continue;
+        }
+        let group = name
+            .as_ref()
+            .and_then(|n| members.get(n.as_str()).map(|indices| (n, indices)))
+            .filter(|(_, indices)| indices.len() > 1);
+        match group {
+            Some((name, indices)) => {
+                let expanded = expanded_groups.contains(name);
+                for &j in indices.iter() {
+                    emitted[j] = true;
+                }
+                rows.push(Row::Group {
+                    name: name.clone(),
+                    thread_indices: indices.clone(),
+                    expanded,
+                });
+                if expanded {
+                    rows.extend(indices.iter().map(|&j| Row::Thread(j)));
+                }
+            }
+            None => {
+                emitted[i] = true;
+                rows.push(Row::Thread(i));
+            }
+        }
+    }
+    Ok(rows)
+}
+
+/// Count the execution samples of the threads per state in each of equally divided time buckets
+pub fn state_counts(
+    profile: &Profile,
+    threads: &[&Thread],
+    bucket_count: usize,
+) -> Vec<StateCounts> {
+    let mut buckets = vec![StateCounts::default(); bucket_count];
+    if bucket_count == 0 {
+        return buckets;
+    }
+    for thread in threads {
+        if let Some(samples) = profile.per_thread_samples.get(&thread.id) {
+            for sample in samples {
+                let ratio = profile.interval.elapsed_ratio(sample.timestamp_nanos);
+                let i = ((ratio * bucket_count as f64) as usize).min(bucket_count - 1);
+                let counts = &mut buckets[i];
+                if !profile.is_valid_sample(thread, sample) {
+                    counts.hidden += 1;
+                } else if sample.event_type == SampleEventType::NativeMethodSample {
+                    counts.native_method += 1;
+                } else {
+                    counts.states[sample.state as usize] += 1;
+                }
+            }
+        }
+    }
+    buckets
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::execution_sample::group::{group_name, rows, state_counts, Row, ThreadGroupConfig};
+    use crate::profile::{Thread, ThreadState};
+    use crate::test_util::load;
+    use regex::Regex;
+    use rustc_hash::FxHashSet;
+
+    fn thread(id: i64, name: &str) -> Thread {
+        Thread {
+            id,
+            os_thread_id: id,
+            java_thread_id: id,
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_group_name() {
+        assert_eq!(
+            group_name("kafka-request-handler-12", None).unwrap(),
+            "kafka-request-handler-"
+        );
+        let regex = Regex::new("^(data-plane-kafka-network-thread)-").unwrap();
+        assert_eq!(
+            group_name(
+                "data-plane-kafka-network-thread-1-ListenerName",
+                Some(&regex)
+            )
+            .unwrap(),
+            "data-plane-kafka-network-thread"
+        );
+        assert_eq!(group_name("main", Some(&regex)), None);
+    }
+
+    #[test]
+    fn test_rows() {
+        let threads = vec![
+            thread(0, "handler-0"),
+            thread(1, "handler-1"),
+            thread(2, "main"),
+            thread(3, "handler-2"),
+        ];
+        let mut config = ThreadGroupConfig::default();
+        let mut expanded = FxHashSet::default();
+        assert_eq!(
+            rows(&threads, &config, &expanded).unwrap(),
+            (0..4).map(Row::Thread).collect::<Vec<_>>()
+        );
+
+        config.enabled = true;
+        let group = |expanded| Row::Group {
+            name: "handler-".to_string(),
+            thread_indices: vec![0, 1, 3],
+            expanded,
+        };
+        assert_eq!(
+            rows(&threads, &config, &expanded).unwrap(),
+            vec![group(false), Row::Thread(2)]
+        );
+
+        expanded.insert("handler-".to_string());
+        assert_eq!(
+            rows(&threads, &config, &expanded).unwrap(),
+            vec![
+                group(true),
+                Row::Thread(0),
+                Row::Thread(1),
+                Row::Thread(3),
+                Row::Thread(2)
+            ]
+        );
+
+        config.pattern = Some("(".to_string());
+        assert!(rows(&threads, &config, &expanded).is_err());
+    }
+
+    #[test]
+    fn test_state_counts() {
+        let profile = load("profiler-wall.jfr");
+
+        let threads: Vec<_> = profile
+            .filtered_threads()
+            .iter()
+            .filter(|t| t.name.starts_with("GC Thread"))
+            .collect();
+        let counts = state_counts(&profile, &threads, profile.column_count);
+        let expected: usize = threads
+            .iter()
+            .map(|t| profile.per_thread_samples[&t.id].len())
+            .sum();
+        assert_eq!(
+            counts.iter().map(|c| c.total() as usize).sum::<usize>(),
+            expected
+        );
+        for state in ThreadState::ALL {
+            assert_eq!(
+                counts
+                    .iter()
+                    .map(|c| c.state(state) as usize)
+                    .sum::<usize>(),
+                threads
+                    .iter()
+                    .flat_map(|t| profile.per_thread_samples[&t.id].iter())
+                    .filter(|s| s.state == state)
+                    .count()
+            );
+        }
+    }
+}
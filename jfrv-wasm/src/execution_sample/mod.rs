@@ -1,15 +1,22 @@
 //! The module implements the renderer for execution samples.
 //! The root module should compile without browser env so should not contain any wasm dependencies.
 
+pub mod group;
+pub mod query;
 #[cfg(target_arch = "wasm32")]
 pub mod render;
+pub mod summary;
 
+use crate::encoding::Encoding;
+use crate::execution_sample::query::Query;
+use crate::execution_sample::summary::{RecordingSummary, SummaryBuilder};
 use crate::profile::{
-    ConstantPoolKey, ExecutionSample, StackFrame, StackTrace, Thread, ThreadState,
+    AllocationSample, ConstantPoolKey, ContentionKind, ContentionSample, ExecutionSample, Sample,
+    SampleEventType, StackFrame, StackTrace, Thread, ThreadState,
 };
-use crate::TimeInterval;
+use crate::{snapshot, TimeInterval};
 use anyhow::{anyhow, Result};
-use jfrs::reader::event::Accessor;
+use jfrs::reader::event::{Accessor, Event};
 use jfrs::reader::value_descriptor::ValueDescriptor;
 use jfrs::reader::{Chunk, JfrReader};
 use log::info;
@@ -17,12 +24,13 @@ use regex::Regex;
 use rustc_hash::{FxHashMap, FxHashSet};
 use serde::{Deserialize, Serialize};
 use std::collections::hash_map::Entry;
+use std::collections::BTreeMap;
 use std::io::Cursor;
 #[cfg(target_arch = "wasm32")]
 use tsify::Tsify;
 
 /// Conditions to filter data
-#[derive(Default, Deserialize, Serialize)]
+#[derive(Clone, Default, Deserialize, Serialize)]
 #[cfg_attr(target_arch = "wasm32", derive(Tsify))]
 #[cfg_attr(target_arch = "wasm32", tsify(into_wasm_abi, from_wasm_abi))]
 #[serde(rename_all = "camelCase")]
@@ -30,188 +38,836 @@ pub struct Filter {
     pub thread_name_regex: Option<String>,
     pub stack_trace_match_regex: Option<String>,
     pub stack_trace_reject_regex: Option<String>,
+    /// Show only execution samples from the event type if specified.
+    /// Allocation and contention samples are hidden then
+    pub event_type: Option<SampleEventType>,
+    /// Show only execution samples in any of the thread states if specified.
+    /// Allocation and contention samples are hidden then
+    pub states: Option<Vec<ThreadState>>,
+    /// Show only samples at or after the timestamp if specified
+    pub start_nanos: Option<i64>,
+    /// Show only samples at or before the timestamp if specified
+    pub end_nanos: Option<i64>,
+    /// Show only samples matching the query if specified. See query module for the syntax
+    pub query: Option<String>,
 }
 
-#[derive(Default)]
+/// Filtered fields are derived from the filter, so they are rebuilt on restoring a snapshot
+#[derive(Default, Deserialize, Serialize)]
 pub struct Profile {
     threads: Vec<Thread>,
+    filter: Filter,
+    #[serde(skip)]
     filtered_threads: Vec<Thread>,
+    #[serde(skip)]
     filtered_stack_trace_keys: FxHashSet<ConstantPoolKey>,
+    #[serde(skip)]
+    filtered_event_type: Option<SampleEventType>,
+    #[serde(skip)]
+    filtered_states: Option<FxHashSet<ThreadState>>,
+    /// Combinations of the thread id, the thread state (None for samples without it)
+    /// and the stack trace which match the query
+    #[serde(skip)]
+    query_matches: Option<FxHashSet<(i64, Option<ThreadState>, ConstantPoolKey)>>,
     pub stack_trace_pool: FxHashMap<ConstantPoolKey, StackTrace>,
+    pub class_name_pool: FxHashMap<ConstantPoolKey, String>,
     pub per_thread_samples: FxHashMap<i64, Vec<ExecutionSample>>,
+    pub per_thread_allocation_samples: FxHashMap<i64, Vec<AllocationSample>>,
+    pub per_thread_contention_samples: FxHashMap<i64, Vec<ContentionSample>>,
     pub column_count: usize,
     pub interval: TimeInterval,
+    pub summary: RecordingSummary,
 }
 
-impl Profile {
-    /// Load and parse execution-sample recording file.
-    pub fn load(&mut self, bytes: Vec<u8>) -> Result<()> {
-        info!("loading JFR file of {} bytes", bytes.len());
+/// Identity of a thread in the recording.
+/// os thread id alone isn't enough because it may be reused by later threads,
+/// so it's combined with java thread id which is never reused in a JVM.
+/// Threads which don't have java thread id (e.g. native threads) are further distinguished by
+/// their lifetime on building the profile.
+#[derive(Copy, Clone, Hash, Eq, PartialEq)]
+struct ThreadKey {
+    java_thread_id: i64,
+    os_thread_id: i64,
+}
 
-        let mut event_count = 0;
-        let mut reader = JfrReader::new(Cursor::new(bytes));
+/// Intermediate state which is shared among chunks while loading a recording
+struct LoadContext {
+    stack_trace_pool: FxHashMap<ConstantPoolKey, StackTrace>,
+    thread_state_pool: FxHashMap<ConstantPoolKey, String>,
+    class_name_pool: FxHashMap<ConstantPoolKey, String>,
+    /// Thread names along with the timestamp when they are observed.
+    /// Only changes of the name are recorded
+    thread_pool: FxHashMap<ThreadKey, Vec<(i64, String)>>,
+    /// Timestamps which a new incarnation of the thread begins at, derived from
+    /// jdk.ThreadStart and jdk.ThreadEnd
+    thread_boundaries: FxHashMap<ThreadKey, Vec<i64>>,
+    per_thread_samples: FxHashMap<ThreadKey, Vec<ExecutionSample>>,
+    per_thread_allocation_samples: FxHashMap<ThreadKey, Vec<AllocationSample>>,
+    per_thread_contention_samples: FxHashMap<ThreadKey, Vec<ContentionSample>>,
+    interval: TimeInterval,
+    summary: SummaryBuilder,
+}
+
+impl Default for LoadContext {
+    fn default() -> Self {
+        Self {
+            stack_trace_pool: FxHashMap::default(),
+            thread_state_pool: FxHashMap::default(),
+            class_name_pool: FxHashMap::default(),
+            thread_pool: FxHashMap::default(),
+            thread_boundaries: FxHashMap::default(),
+            per_thread_samples: FxHashMap::default(),
+            per_thread_allocation_samples: FxHashMap::default(),
+            per_thread_contention_samples: FxHashMap::default(),
+            interval: TimeInterval::new(i64::MAX, 0),
+            summary: SummaryBuilder::default(),
+        }
+    }
+}
+
+impl LoadContext {
+    fn update_interval(&mut self, timestamp_nanos: i64) {
+        self.interval.start_nanos = self.interval.start_nanos.min(timestamp_nanos);
+        self.interval.end_nanos = self.interval.end_nanos.max(timestamp_nanos);
+    }
+
+    /// Split samples of each thread into incarnations by the lifetime boundaries,
+    /// then give each incarnation its own thread.
+    /// Returns the threads sorted by name and per-thread samples sorted by timestamp.
+    fn build_threads(&mut self) -> ThreadTable {
+        let mut table = ThreadTable::default();
+        let mut incarnations: Vec<Incarnation> = vec![];
+
+        for (key, mut names) in std::mem::take(&mut self.thread_pool) {
+            let mut boundaries = self.thread_boundaries.remove(&key).unwrap_or_default();
+            boundaries.sort_unstable();
+            names.sort_by_key(|(timestamp, _)| *timestamp);
+
+            let mut per_incarnation: BTreeMap<usize, Incarnation> = BTreeMap::new();
+            for sample in self.per_thread_samples.remove(&key).unwrap_or_default() {
+                per_incarnation
+                    .entry(Self::incarnation_index(&boundaries, sample.timestamp_nanos))
+                    .or_default()
+                    .samples
+                    .push(sample);
+            }
+            for sample in self
+                .per_thread_allocation_samples
+                .remove(&key)
+                .unwrap_or_default()
+            {
+                per_incarnation
+                    .entry(Self::incarnation_index(&boundaries, sample.timestamp_nanos))
+                    .or_default()
+                    .allocation_samples
+                    .push(sample);
+            }
+            for sample in self
+                .per_thread_contention_samples
+                .remove(&key)
+                .unwrap_or_default()
+            {
+                per_incarnation
+                    .entry(Self::incarnation_index(&boundaries, sample.timestamp_nanos))
+                    .or_default()
+                    .contention_samples
+                    .push(sample);
+            }
+            for (i, mut incarnation) in per_incarnation {
+                incarnation.sort();
+                let start = if i == 0 { i64::MIN } else { boundaries[i - 1] };
+                let first_timestamp = incarnation.first_timestamp().unwrap_or(start);
+                // prefer the name observed during the incarnation, then the last one before it
+                incarnation.thread = Thread {
+                    id: 0,
+                    os_thread_id: key.os_thread_id,
+                    java_thread_id: key.java_thread_id,
+                    name: names
+                        .iter()
+                        .find(|(timestamp, _)| *timestamp >= start)
+                        .filter(|(timestamp, _)| {
+                            boundaries.get(i).map(|end| timestamp < end).unwrap_or(true)
+                        })
+                        .or_else(|| names.iter().rev().find(|(timestamp, _)| *timestamp < start))
+                        .or_else(|| names.first())
+                        .map(|(_, name)| name.clone())
+                        .unwrap_or_default(),
+                };
+                incarnation.first_timestamp = first_timestamp;
+                incarnations.push(incarnation);
+            }
+        }
+
+        incarnations.sort_by(|a, b| {
+            a.thread
+                .name
+                .cmp(&b.thread.name)
+                .then(a.first_timestamp.cmp(&b.first_timestamp))
+        });
+        for (i, mut incarnation) in incarnations.into_iter().enumerate() {
+            let id = i as i64;
+            incarnation.thread.id = id;
+            table.column_count = table
+                .column_count
+                .max(incarnation.samples.len())
+                .max(incarnation.allocation_samples.len())
+                .max(incarnation.contention_samples.len());
+            if !incarnation.samples.is_empty() {
+                table.per_thread_samples.insert(id, incarnation.samples);
+            }
+            if !incarnation.allocation_samples.is_empty() {
+                table
+                    .per_thread_allocation_samples
+                    .insert(id, incarnation.allocation_samples);
+            }
+            if !incarnation.contention_samples.is_empty() {
+                table
+                    .per_thread_contention_samples
+                    .insert(id, incarnation.contention_samples);
+            }
+            table.threads.push(incarnation.thread);
+        }
+        table
+    }
+
+    /// The index of the incarnation which the timestamp belongs to
+    fn incarnation_index(boundaries: &[i64], timestamp_nanos: i64) -> usize {
+        boundaries.partition_point(|b| *b <= timestamp_nanos)
+    }
+}
+
+/// Samples of an incarnation of a thread
+#[derive(Default)]
+struct Incarnation {
+    thread: Thread,
+    first_timestamp: i64,
+    samples: Vec<ExecutionSample>,
+    allocation_samples: Vec<AllocationSample>,
+    contention_samples: Vec<ContentionSample>,
+}
+
+impl Incarnation {
+    fn sort(&mut self) {
+        self.samples.sort_by_key(|s| s.timestamp_nanos);
+        self.allocation_samples.sort_by_key(|s| s.timestamp_nanos);
+        self.contention_samples.sort_by_key(|s| s.timestamp_nanos);
+    }
 
-        let mut stack_trace_pool = FxHashMap::default();
-        let mut thread_state_pool: FxHashMap<ConstantPoolKey, String> = FxHashMap::default();
-        let mut thread_pool = FxHashMap::default();
-        let mut per_thread_samples = FxHashMap::default();
-        let mut column_count = 0;
-        let mut interval = TimeInterval::new(i64::MAX, 0);
+    fn first_timestamp(&self) -> Option<i64> {
+        [
+            self.samples.first().map(|s| s.timestamp_nanos),
+            self.allocation_samples.first().map(|s| s.timestamp_nanos),
+            self.contention_samples.first().map(|s| s.timestamp_nanos),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+}
+
+/// Threads and their samples keyed by the thread id
+#[derive(Default)]
+struct ThreadTable {
+    threads: Vec<Thread>,
+    per_thread_samples: FxHashMap<i64, Vec<ExecutionSample>>,
+    per_thread_allocation_samples: FxHashMap<i64, Vec<AllocationSample>>,
+    per_thread_contention_samples: FxHashMap<i64, Vec<ContentionSample>>,
+    column_count: usize,
+}
+
+/// Incremental loader which parses a recording chunk by chunk.
+///
+/// The input can be fed in arbitrary-sized pieces (they don't need to be aligned to JFR chunk
+/// boundaries). Each JFR chunk is parsed as soon as it's fully buffered and its bytes are
+/// released right after samples are extracted, so the memory usage is bounded by the chunk size
+/// rather than the file size.
+#[derive(Default)]
+pub struct ProfileLoader {
+    ctx: LoadContext,
+    buffer: Vec<u8>,
+    chunk_seq: usize,
+    event_count: usize,
+}
+
+impl ProfileLoader {
+    const CHUNK_MAGIC: &'static [u8] = b"FLR\0";
+    /// magic + version + chunk size
+    const CHUNK_PREAMBLE_SIZE: usize = 4 + 4 + 8;
 
-        for (chunk_seq, reader) in reader.chunks().enumerate() {
+    /// Feed the next piece of the (uncompressed) recording.
+    pub fn feed_chunk(&mut self, bytes: Vec<u8>) -> Result<()> {
+        if self.buffer.is_empty() {
+            self.buffer = bytes;
+        } else {
+            self.buffer.extend_from_slice(&bytes);
+        }
+
+        let mut offset = 0;
+        while let Some(chunk_size) = Self::complete_chunk_size(&self.buffer[offset..])? {
+            let chunk_bytes = &self.buffer[offset..(offset + chunk_size)];
+            Self::load_chunk(
+                &mut self.ctx,
+                self.chunk_seq,
+                chunk_bytes,
+                &mut self.event_count,
+            )?;
+            self.chunk_seq += 1;
+            offset += chunk_size;
+        }
+
+        if offset > 0 {
+            // copy the incomplete remainder so that the memory of parsed chunks is released
+            self.buffer = self.buffer[offset..].to_vec();
+        }
+        Ok(())
+    }
+
+    /// Finish loading and build the profile.
+    /// Fails if the fed bytes end in the middle of a chunk.
+    pub fn finish(self) -> Result<Profile> {
+        if !self.buffer.is_empty() {
+            return Err(anyhow!(
+                "Recording is truncated: {} bytes of incomplete chunk remain",
+                self.buffer.len()
+            ));
+        }
+        let mut ctx = self.ctx;
+        let table = ctx.build_threads();
+        let summary = ctx
+            .summary
+            .build(&ctx.stack_trace_pool, &table.per_thread_samples);
+        let profile = Profile {
+            filtered_threads: table.threads.to_vec(),
+            filtered_stack_trace_keys: ctx.stack_trace_pool.keys().cloned().collect(),
+            threads: table.threads,
+            stack_trace_pool: ctx.stack_trace_pool,
+            class_name_pool: ctx.class_name_pool,
+            per_thread_samples: table.per_thread_samples,
+            per_thread_allocation_samples: table.per_thread_allocation_samples,
+            per_thread_contention_samples: table.per_thread_contention_samples,
+            column_count: table.column_count,
+            interval: ctx.interval,
+            summary,
+            ..Default::default()
+        };
+
+        info!(
+            "Loaded {} events from {} chunks",
+            self.event_count, self.chunk_seq
+        );
+
+        Ok(profile)
+    }
+
+    /// Returns the size of the chunk at the beginning of the bytes if it's fully available.
+    /// Fails as soon as the magic turns out to be wrong, rather than buffering the input until the end.
+    fn complete_chunk_size(bytes: &[u8]) -> Result<Option<usize>> {
+        let magic_len = bytes.len().min(Self::CHUNK_MAGIC.len());
+        if bytes[..magic_len] != Self::CHUNK_MAGIC[..magic_len] {
+            return Err(match Encoding::detect(bytes) {
+                Encoding::Uncompressed => anyhow!("Not a JFR recording"),
+                encoding => anyhow!("Recording must be decompressed first: {:?}", encoding),
+            });
+        }
+        if bytes.len() < Self::CHUNK_PREAMBLE_SIZE {
+            return Ok(None);
+        }
+        let mut size = [0u8; 8];
+        size.copy_from_slice(&bytes[8..16]);
+        let chunk_size = usize::try_from(i64::from_be_bytes(size))
+            .ok()
+            .filter(|&s| s >= Self::CHUNK_PREAMBLE_SIZE)
+            .ok_or_else(|| anyhow!("Invalid chunk size"))?;
+        Ok(if bytes.len() >= chunk_size {
+            Some(chunk_size)
+        } else {
+            None
+        })
+    }
+
+    fn load_chunk(
+        ctx: &mut LoadContext,
+        chunk_seq: usize,
+        bytes: &[u8],
+        event_count: &mut usize,
+    ) -> Result<()> {
+        let mut reader = JfrReader::new(Cursor::new(bytes));
+        for reader in reader.chunks() {
             let (mut reader, chunk) = reader?;
-            let start_nanos = chunk.header.start_time_nanos;
-            let start_ticks = chunk.header.start_ticks;
-            let ticks_per_nanos = (chunk.header.ticks_per_second as f64) / 1_000_000_000.0;
+            ctx.summary.add_chunk(&chunk);
 
             for event in reader.events(&chunk) {
                 let event = event?;
-                if event.class.name() != "jdk.ExecutionSample" {
-                    continue;
+                ctx.summary.add_event(&chunk, &event);
+                match event.class.name() {
+                    Profile::EVENT_EXECUTION_SAMPLE | Profile::EVENT_NATIVE_METHOD_SAMPLE => {
+                        Profile::load_execution_sample(ctx, chunk_seq, &chunk, &event)?
+                    }
+                    Profile::EVENT_ALLOCATION_SAMPLE
+                    | Profile::EVENT_ALLOCATION_IN_NEW_TLAB
+                    | Profile::EVENT_ALLOCATION_OUTSIDE_TLAB => {
+                        Profile::load_allocation_sample(ctx, chunk_seq, &chunk, &event)?
+                    }
+                    Profile::EVENT_MONITOR_ENTER | Profile::EVENT_THREAD_PARK => {
+                        Profile::load_contention_sample(ctx, chunk_seq, &chunk, &event)?
+                    }
+                    Profile::EVENT_THREAD_START | Profile::EVENT_THREAD_END => {
+                        Profile::load_thread_lifetime(ctx, &chunk, &event)?
+                    }
+                    _ => continue,
                 }
-                event_count += 1;
+                *event_count += 1;
+            }
+        }
+        Ok(())
+    }
+}
 
-                let stack_trace_key =
-                    Self::get_constant_pool(chunk_seq, &event.value(), "stackTrace")?;
-                let thread_state_key = Self::get_constant_pool(chunk_seq, &event.value(), "state")?;
-                let thread_accessor = event
-                    .value()
-                    .get_field("sampledThread")
-                    .ok_or_else(|| anyhow!("Failed to get sampled thread"))?;
-                let os_thread_id = thread_accessor
-                    .get_field("osThreadId")
-                    .and_then(|i| i64::try_from(i.value).ok())
-                    .ok_or_else(|| anyhow!("Failed to get osThreadId"))?;
-
-                if let Entry::Vacant(e) = stack_trace_pool.entry(stack_trace_key) {
-                    e.insert(Self::parse_stack_trace(&stack_trace_key, &chunk)?);
-                }
-                // FIXME: Thread name doesn't show correctly if same os_thread_id is reused
-                if let Entry::Vacant(e) = thread_pool.entry(os_thread_id) {
-                    e.insert(Self::parse_thread(os_thread_id, &thread_accessor)?);
-                }
-                // we can't use or_insert_with because parse_thread_state may fail
-                let state = match thread_state_pool.get(&thread_state_key) {
-                    Some(state) => state.as_str().into(),
-                    None => {
-                        let state_str = Self::parse_thread_state(&thread_state_key, &chunk)?;
-                        let state: ThreadState = state_str.as_str().into();
-                        thread_state_pool.insert(thread_state_key, state_str);
-                        state
-                    }
-                };
+impl Profile {
+    const EVENT_EXECUTION_SAMPLE: &'static str = "jdk.ExecutionSample";
+    const EVENT_NATIVE_METHOD_SAMPLE: &'static str = "jdk.NativeMethodSample";
+    const EVENT_ALLOCATION_SAMPLE: &'static str = "jdk.ObjectAllocationSample";
+    const EVENT_ALLOCATION_IN_NEW_TLAB: &'static str = "jdk.ObjectAllocationInNewTLAB";
+    const EVENT_ALLOCATION_OUTSIDE_TLAB: &'static str = "jdk.ObjectAllocationOutsideTLAB";
+    const EVENT_MONITOR_ENTER: &'static str = "jdk.JavaMonitorEnter";
+    const EVENT_THREAD_PARK: &'static str = "jdk.ThreadPark";
+    const EVENT_THREAD_START: &'static str = "jdk.ThreadStart";
+    const EVENT_THREAD_END: &'static str = "jdk.ThreadEnd";
 
-                let tick = event
-                    .value()
-                    .get_field("startTime")
-                    .and_then(|s| i64::try_from(s.value).ok())
-                    .ok_or_else(|| anyhow!("Failed to get startTime"))?;
-                let timestamp_nanos =
-                    start_nanos + ((tick - start_ticks) as f64 / ticks_per_nanos) as i64;
-                let timestamp_millis = timestamp_nanos / 1000 / 1000;
-
-                let samples = per_thread_samples
-                    .entry(os_thread_id)
-                    .or_insert_with(Vec::new);
-                samples.push(ExecutionSample {
-                    timestamp_nanos,
-                    state,
-                    stack_trace_key,
-                });
-
-                interval.start_millis = interval.start_millis.min(timestamp_millis);
-                interval.end_millis = interval.end_millis.max(timestamp_millis);
-                column_count = column_count.max(samples.len());
+    /// Load and parse execution-sample recording file.
+    pub fn load(&mut self, bytes: Vec<u8>) -> Result<()> {
+        info!("loading JFR file of {} bytes", bytes.len());
+
+        let mut loader = ProfileLoader::default();
+        loader.feed_chunk(bytes)?;
+        *self = loader.finish()?;
+        Ok(())
+    }
+
+    /// Serialize the profile along with the active filter into a snapshot
+    pub fn snapshot(&self) -> Result<Vec<u8>> {
+        snapshot::save(snapshot::Kind::ExecutionSample, self)
+    }
+
+    /// Restore the profile from the snapshot then re-apply the filter
+    pub fn restore(bytes: &[u8]) -> Result<Self> {
+        let mut profile: Self = snapshot::restore(snapshot::Kind::ExecutionSample, bytes)?;
+        profile.apply_filter(profile.filter.clone())?;
+        Ok(profile)
+    }
+
+    fn load_execution_sample(
+        ctx: &mut LoadContext,
+        chunk_seq: usize,
+        chunk: &Chunk,
+        event: &Event<'_>,
+    ) -> Result<()> {
+        let stack_trace_key = Self::get_constant_pool(chunk_seq, &event.value(), "stackTrace")?;
+        let thread_state_key = Self::get_constant_pool(chunk_seq, &event.value(), "state")?;
+        let timestamp_nanos = Self::get_timestamp_nanos(chunk, event)?;
+        let thread_key = Self::register_thread(ctx, event, "sampledThread", timestamp_nanos)?;
+        Self::register_stack_trace(ctx, stack_trace_key, chunk)?;
+
+        // we can't use or_insert_with because parse_thread_state may fail
+        let state = match ctx.thread_state_pool.get(&thread_state_key) {
+            Some(state) => state.as_str().into(),
+            None => {
+                let state_str = Self::parse_thread_state(&thread_state_key, chunk)?;
+                let state: ThreadState = state_str.as_str().into();
+                ctx.thread_state_pool.insert(thread_state_key, state_str);
+                state
             }
+        };
+
+        let event_type = if event.class.name() == Self::EVENT_NATIVE_METHOD_SAMPLE {
+            SampleEventType::NativeMethodSample
+        } else {
+            SampleEventType::ExecutionSample
+        };
+
+        ctx.per_thread_samples
+            .entry(thread_key)
+            .or_default()
+            .push(ExecutionSample {
+                timestamp_nanos,
+                event_type,
+                state,
+                stack_trace_key,
+            });
+        ctx.update_interval(timestamp_nanos);
+
+        Ok(())
+    }
+
+    fn load_allocation_sample(
+        ctx: &mut LoadContext,
+        chunk_seq: usize,
+        chunk: &Chunk,
+        event: &Event<'_>,
+    ) -> Result<()> {
+        // stack trace is null when it's disabled in the recording settings.
+        // such events can't be placed in flame graphs so we just skip them
+        let stack_trace_key =
+            match Self::get_nullable_constant_pool(chunk_seq, &event.value(), "stackTrace") {
+                Some(key) => key,
+                None => return Ok(()),
+            };
+        let timestamp_nanos = Self::get_timestamp_nanos(chunk, event)?;
+        let thread_key = Self::register_thread(ctx, event, "eventThread", timestamp_nanos)?;
+        Self::register_stack_trace(ctx, stack_trace_key, chunk)?;
+        let object_class_key =
+            Self::get_nullable_constant_pool(chunk_seq, &event.value(), "objectClass")
+                .and_then(|key| Self::register_class_name(ctx, key, chunk));
+
+        // jdk.ObjectAllocationSample (JDK 16+) carries the sampled size as "weight"
+        let size_field = if event.class.name() == Self::EVENT_ALLOCATION_SAMPLE {
+            "weight"
+        } else {
+            "allocationSize"
+        };
+        let allocation_size = event
+            .value()
+            .get_field(size_field)
+            .and_then(|s| i64::try_from(s.value).ok())
+            .ok_or_else(|| anyhow!("Failed to get {}", size_field))?;
+
+        ctx.per_thread_allocation_samples
+            .entry(thread_key)
+            .or_default()
+            .push(AllocationSample {
+                timestamp_nanos,
+                allocation_size,
+                outside_tlab: event.class.name() == Self::EVENT_ALLOCATION_OUTSIDE_TLAB,
+                object_class_key,
+                stack_trace_key,
+            });
+        ctx.update_interval(timestamp_nanos);
+
+        Ok(())
+    }
+
+    fn load_contention_sample(
+        ctx: &mut LoadContext,
+        chunk_seq: usize,
+        chunk: &Chunk,
+        event: &Event<'_>,
+    ) -> Result<()> {
+        let (kind, class_field) = if event.class.name() == Self::EVENT_MONITOR_ENTER {
+            (ContentionKind::MonitorEnter, "monitorClass")
+        } else {
+            (ContentionKind::ThreadPark, "parkedClass")
+        };
+        // same as allocation samples, events without stack trace are skipped
+        let stack_trace_key =
+            match Self::get_nullable_constant_pool(chunk_seq, &event.value(), "stackTrace") {
+                Some(key) => key,
+                None => return Ok(()),
+            };
+        let timestamp_nanos = Self::get_timestamp_nanos(chunk, event)?;
+        let thread_key = Self::register_thread(ctx, event, "eventThread", timestamp_nanos)?;
+        Self::register_stack_trace(ctx, stack_trace_key, chunk)?;
+
+        // monitor class is null (i.e. not in the pool) in some cases,
+        // e.g. when a thread is parked without a blocker object
+        let monitor_class_key =
+            Self::get_nullable_constant_pool(chunk_seq, &event.value(), class_field)
+                .and_then(|key| Self::register_class_name(ctx, key, chunk));
+
+        // the owner doesn't necessarily have samples, so it's only referred
+        // without being registered as a thread row
+        let previous_owner = match kind {
+            ContentionKind::MonitorEnter => event
+                .value()
+                .get_field("previousOwner")
+                .and_then(|owner| Self::parse_thread_key(&owner).ok())
+                .map(|key| key.java_thread_id),
+            ContentionKind::ThreadPark => None,
+        };
+
+        let duration_ticks = event
+            .value()
+            .get_field("duration")
+            .and_then(|s| i64::try_from(s.value).ok())
+            .ok_or_else(|| anyhow!("Failed to get duration"))?;
+        let duration_nanos = Self::ticks_to_nanos(chunk, duration_ticks);
+
+        ctx.per_thread_contention_samples
+            .entry(thread_key)
+            .or_default()
+            .push(ContentionSample {
+                timestamp_nanos,
+                duration_nanos,
+                kind,
+                monitor_class_key,
+                previous_owner,
+                stack_trace_key,
+            });
+        ctx.update_interval(timestamp_nanos);
+        ctx.update_interval(timestamp_nanos + duration_nanos);
+
+        Ok(())
+    }
+
+    /// Mark the beginning of a new incarnation of the thread on its start or after its end
+    fn load_thread_lifetime(ctx: &mut LoadContext, chunk: &Chunk, event: &Event<'_>) -> Result<()> {
+        let timestamp_nanos = Self::get_timestamp_nanos(chunk, event)?;
+        // older JDKs don't have "thread" field in jdk.ThreadEnd
+        let thread_accessor = event
+            .value()
+            .get_field("thread")
+            .or_else(|| event.value().get_field("eventThread"))
+            .ok_or_else(|| anyhow!("Failed to get thread"))?;
+        let key = Self::parse_thread_key(&thread_accessor)?;
+        let boundary = if event.class.name() == Self::EVENT_THREAD_END {
+            timestamp_nanos + 1
+        } else {
+            timestamp_nanos
+        };
+        ctx.thread_boundaries.entry(key).or_default().push(boundary);
+        Ok(())
+    }
+
+    /// Register the thread referred by the field to the pool and returns its key.
+    fn register_thread(
+        ctx: &mut LoadContext,
+        event: &Event<'_>,
+        field_name: &'static str,
+        timestamp_nanos: i64,
+    ) -> Result<ThreadKey> {
+        let thread_accessor = event
+            .value()
+            .get_field(field_name)
+            .ok_or_else(|| anyhow!("Failed to get {}", field_name))?;
+        let key = Self::parse_thread_key(&thread_accessor)?;
+        let name = Self::parse_thread_name(&thread_accessor)?;
+
+        let names = ctx.thread_pool.entry(key).or_default();
+        if names.last().map(|(_, n)| n != name).unwrap_or(true) {
+            names.push((timestamp_nanos, name.to_string()));
         }
+        Ok(key)
+    }
 
-        self.threads = thread_pool.into_values().collect();
-        self.threads.sort_by(|a, b| a.name.cmp(&b.name));
-        self.filtered_threads = self.threads.to_vec();
-        self.stack_trace_pool = stack_trace_pool;
-        self.filtered_stack_trace_keys = self.stack_trace_pool.keys().cloned().collect();
-        self.per_thread_samples = per_thread_samples;
-        for (_, v) in self.per_thread_samples.iter_mut() {
-            v.sort_by_key(|s| s.timestamp_nanos)
+    fn register_stack_trace(
+        ctx: &mut LoadContext,
+        stack_trace_key: ConstantPoolKey,
+        chunk: &Chunk,
+    ) -> Result<()> {
+        if let Entry::Vacant(e) = ctx.stack_trace_pool.entry(stack_trace_key) {
+            e.insert(Self::parse_stack_trace(&stack_trace_key, chunk)?);
         }
-        self.column_count = column_count;
-        self.interval = interval;
+        Ok(())
+    }
 
-        info!("Loaded {} events", event_count);
+    /// Register the class name to the pool and returns the key if the class could be resolved
+    fn register_class_name(
+        ctx: &mut LoadContext,
+        class_key: ConstantPoolKey,
+        chunk: &Chunk,
+    ) -> Option<ConstantPoolKey> {
+        match ctx.class_name_pool.entry(class_key) {
+            Entry::Occupied(_) => Some(class_key),
+            Entry::Vacant(e) => Self::parse_class_name(&class_key, chunk).ok().map(|name| {
+                e.insert(name);
+                class_key
+            }),
+        }
+    }
 
-        Ok(())
+    fn get_timestamp_nanos(chunk: &Chunk, event: &Event<'_>) -> Result<i64> {
+        let tick = event
+            .value()
+            .get_field("startTime")
+            .and_then(|s| i64::try_from(s.value).ok())
+            .ok_or_else(|| anyhow!("Failed to get startTime"))?;
+        Ok(chunk.header.start_time_nanos
+            + Self::ticks_to_nanos(chunk, tick - chunk.header.start_ticks))
+    }
+
+    fn ticks_to_nanos(chunk: &Chunk, ticks: i64) -> i64 {
+        ticks_to_nanos(ticks, chunk.header.ticks_per_second)
     }
 
     pub fn filtered_threads(&self) -> &Vec<Thread> {
         &self.filtered_threads
     }
 
-    pub fn is_valid_sample(&self, sample: &ExecutionSample) -> bool {
-        self.filtered_stack_trace_keys
-            .contains(&sample.stack_trace_key)
+    /// The filter which is currently applied
+    pub fn filter(&self) -> &Filter {
+        &self.filter
     }
 
+    /// Whether the sample taken on the thread passes the filter.
+    /// Thread name is not checked here. Iterate filtered_threads for it
+    pub fn is_valid_sample<S: Sample>(&self, thread: &Thread, sample: &S) -> bool {
+        self.filtered_event_type
+            .map(|t| sample.event_type() == Some(t))
+            .unwrap_or(true)
+            && self
+                .filtered_states
+                .as_ref()
+                .map(|states| sample.state().is_some_and(|s| states.contains(&s)))
+                .unwrap_or(true)
+            && self.is_in_time_range(sample.timestamp_nanos())
+            && self
+                .filtered_stack_trace_keys
+                .contains(&sample.stack_trace_key())
+            && self
+                .query_matches
+                .as_ref()
+                .map(|m| m.contains(&(thread.id, sample.state(), sample.stack_trace_key())))
+                .unwrap_or(true)
+    }
+
+    /// Whether the timestamp is within the time range of the filter
+    pub fn is_in_time_range(&self, timestamp_nanos: i64) -> bool {
+        self.filter
+            .start_nanos
+            .map(|start| start <= timestamp_nanos)
+            .unwrap_or(true)
+            && self
+                .filter
+                .end_nanos
+                .map(|end| timestamp_nanos <= end)
+                .unwrap_or(true)
+    }
+
+    /// Narrow the samples sorted by timestamp down to the time range of the filter
+    pub fn samples_in_time_range<'a, S: Sample>(&self, samples: &'a [S]) -> &'a [S] {
+        let from = match self.filter.start_nanos {
+            Some(start) => samples.partition_point(|s| s.timestamp_nanos() < start),
+            None => 0,
+        };
+        let to = match self.filter.end_nanos {
+            Some(end) => samples.partition_point(|s| s.timestamp_nanos() <= end),
+            None => samples.len(),
+        };
+        &samples[from..to.max(from)]
+    }
+
+    /// Apply the filter. If the filter is invalid, the profile is left as it was
     pub fn apply_filter(&mut self, filter: Filter) -> Result<()> {
-        if let Some(regex) = &filter.thread_name_regex {
-            let regex = Regex::new(regex.as_str())?;
-            info!("regex: {:?}", regex);
-            self.filtered_threads = self
-                .threads
-                .iter()
-                .filter(|t| regex.is_match(t.name.as_str()))
-                .cloned()
-                .collect();
-            info!("filtered");
-        } else {
-            self.filtered_threads = self.threads.to_vec();
+        if let (Some(start), Some(end)) = (filter.start_nanos, filter.end_nanos) {
+            if start > end {
+                return Err(anyhow!("Invalid time range: {} > {}", start, end));
+            }
         }
-
-        let stack_trace_match_regex = if let Some(regex) = &filter.stack_trace_match_regex {
-            Some(Regex::new(regex.as_str())?)
-        } else {
-            None
+        let thread_name_regex = Self::compile_regex(&filter.thread_name_regex)?;
+        let stack_trace_match_regex = Self::compile_regex(&filter.stack_trace_match_regex)?;
+        let stack_trace_reject_regex = Self::compile_regex(&filter.stack_trace_reject_regex)?;
+        let query = match &filter.query {
+            Some(query) => Some(Query::parse(query)?),
+            None => None,
         };
-        let stack_trace_reject_regex = if let Some(regex) = &filter.stack_trace_reject_regex {
-            Some(Regex::new(regex.as_str())?)
-        } else {
-            None
+
+        let query_matches = query.map(|query| self.evaluate_query(&query));
+
+        let filtered_threads = match thread_name_regex {
+            Some(regex) => {
+                info!("regex: {:?}", regex);
+                self.threads
+                    .iter()
+                    .filter(|t| regex.is_match(t.name.as_str()))
+                    .cloned()
+                    .collect()
+            }
+            None => self.threads.to_vec(),
         };
 
-        if stack_trace_match_regex.is_none() && stack_trace_reject_regex.is_none() {
-            // fast path
-            self.filtered_stack_trace_keys = self.stack_trace_pool.keys().cloned().collect();
-        } else {
-            self.filtered_stack_trace_keys = self
-                .stack_trace_pool
-                .iter()
-                .map(|(k, v)| {
-                    (
-                        k,
-                        v.frames
-                            .iter()
-                            .map(|f| format!("{}.{}", f.type_name, f.method_name))
-                            .collect::<Vec<_>>()
-                            .join("\n"),
-                    )
-                })
-                .filter(|(_k, v)| {
-                    stack_trace_match_regex
-                        .as_ref()
-                        .map(|r| r.is_match(v))
-                        .unwrap_or(true)
-                        && stack_trace_reject_regex
+        let filtered_stack_trace_keys =
+            if stack_trace_match_regex.is_none() && stack_trace_reject_regex.is_none() {
+                // fast path
+                self.stack_trace_pool.keys().cloned().collect()
+            } else {
+                self.stack_trace_pool
+                    .iter()
+                    .map(|(k, v)| {
+                        (
+                            k,
+                            v.frames
+                                .iter()
+                                .map(|f| format!("{}.{}", f.type_name, f.method_name))
+                                .collect::<Vec<_>>()
+                                .join("\n"),
+                        )
+                    })
+                    .filter(|(_k, v)| {
+                        stack_trace_match_regex
                             .as_ref()
-                            .map(|r| !r.is_match(v))
+                            .map(|r| r.is_match(v))
                             .unwrap_or(true)
-                })
-                .map(|(k, _v)| k)
-                .cloned()
-                .collect();
-        }
+                            && stack_trace_reject_regex
+                                .as_ref()
+                                .map(|r| !r.is_match(v))
+                                .unwrap_or(true)
+                    })
+                    .map(|(k, _v)| k)
+                    .cloned()
+                    .collect()
+            };
 
+        self.query_matches = query_matches;
+        self.filtered_event_type = filter.event_type;
+        self.filtered_states = filter
+            .states
+            .as_ref()
+            .map(|states| states.iter().cloned().collect());
+        self.filtered_threads = filtered_threads;
+        self.filtered_stack_trace_keys = filtered_stack_trace_keys;
+        self.filter = filter;
         Ok(())
     }
 
+    fn compile_regex(regex: &Option<String>) -> Result<Option<Regex>> {
+        Ok(match regex {
+            Some(regex) => Some(Regex::new(regex.as_str())?),
+            None => None,
+        })
+    }
+
+    /// Evaluate the query against every distinct combination of the thread, the thread state
+    /// and the stack trace of all kinds of samples
+    fn evaluate_query(
+        &self,
+        query: &Query,
+    ) -> FxHashSet<(i64, Option<ThreadState>, ConstantPoolKey)> {
+        let mut evaluated = FxHashSet::default();
+        let mut matches = FxHashSet::default();
+        for thread in self.threads.iter() {
+            let execution_samples = self.per_thread_samples.get(&thread.id);
+            let allocation_samples = self.per_thread_allocation_samples.get(&thread.id);
+            let contention_samples = self.per_thread_contention_samples.get(&thread.id);
+            let keys = execution_samples
+                .into_iter()
+                .flatten()
+                .map(|s| (s.state(), s.stack_trace_key()))
+                .chain(
+                    allocation_samples
+                        .into_iter()
+                        .flatten()
+                        .map(|s| (s.state(), s.stack_trace_key())),
+                )
+                .chain(
+                    contention_samples
+                        .into_iter()
+                        .flatten()
+                        .map(|s| (s.state(), s.stack_trace_key())),
+                );
+            for (state, stack_trace_key) in keys {
+                let key = (thread.id, state, stack_trace_key);
+                if !evaluated.insert(key) {
+                    continue;
+                }
+                if let Some(stack_trace) = self.stack_trace_pool.get(&stack_trace_key) {
+                    if query.matches(stack_trace, thread, state) {
+                        matches.insert(key);
+                    }
+                }
+            }
+        }
+        matches
+    }
+
     fn get_constant_pool(
         chunk_seq: usize,
         accessor: &Accessor<'_>,
@@ -226,6 +882,17 @@ impl Profile {
         }
     }
 
+    /// Same as get_constant_pool, but returns None if the field is null,
+    /// which is represented as an index that doesn't exist in the constant pool
+    fn get_nullable_constant_pool(
+        chunk_seq: usize,
+        accessor: &Accessor<'_>,
+        field_name: &'static str,
+    ) -> Option<ConstantPoolKey> {
+        accessor.get_field(field_name)?;
+        Self::get_constant_pool(chunk_seq, accessor, field_name).ok()
+    }
+
     fn parse_stack_trace(key: &ConstantPoolKey, chunk: &Chunk) -> Result<StackTrace> {
         let desc = ValueDescriptor::ConstantPool {
             class_id: key.class_id,
@@ -239,32 +906,68 @@ impl Profile {
             .and_then(|f| f.as_iter())
             .ok_or_else(|| anyhow!("failed to get stack frames"))?
         {
-            frames.push(StackFrame::new(
-                f.get_field("method")
-                    .and_then(|m| m.get_field("type"))
-                    .and_then(|t| t.get_field("name"))
-                    .and_then(|n| n.get_field("string"))
-                    .and_then(|s| <&str>::try_from(s.value).ok())
-                    .ok_or_else(|| anyhow!("failed to get type name"))?
-                    .to_string(),
-                f.get_field("method")
-                    .and_then(|t| t.get_field("name"))
-                    .and_then(|n| n.get_field("string"))
-                    .and_then(|s| <&str>::try_from(s.value).ok())
-                    .ok_or_else(|| anyhow!("failed to get method name"))?
-                    .to_string(),
-                f.get_field("type")
-                    .and_then(|t| t.get_field("description"))
-                    .and_then(|s| <&str>::try_from(s.value).ok())
-                    .map(|s| s.into())
-                    .ok_or_else(|| anyhow!("failed to get frame type"))?,
-                f.get_field("lineNumber")
-                    .and_then(|l| <i32>::try_from(l.value).ok())
-                    .ok_or_else(|| anyhow!("failed to get line number"))?,
-            ));
+            frames.push(
+                StackFrame::new(
+                    f.get_field("method")
+                        .and_then(|m| m.get_field("type"))
+                        .and_then(|t| t.get_field("name"))
+                        .and_then(|n| n.get_field("string"))
+                        .and_then(|s| <&str>::try_from(s.value).ok())
+                        .ok_or_else(|| anyhow!("failed to get type name"))?
+                        .to_string(),
+                    f.get_field("method")
+                        .and_then(|t| t.get_field("name"))
+                        .and_then(|n| n.get_field("string"))
+                        .and_then(|s| <&str>::try_from(s.value).ok())
+                        .ok_or_else(|| anyhow!("failed to get method name"))?
+                        .to_string(),
+                    f.get_field("type")
+                        .and_then(|t| t.get_field("description"))
+                        .and_then(|s| <&str>::try_from(s.value).ok())
+                        .map(|s| s.into())
+                        .ok_or_else(|| anyhow!("failed to get frame type"))?,
+                    f.get_field("lineNumber")
+                        .and_then(|l| <i32>::try_from(l.value).ok())
+                        .ok_or_else(|| anyhow!("failed to get line number"))?,
+                )
+                .with_method_signature(
+                    f.get_field("method")
+                        .and_then(|m| m.get_field("descriptor"))
+                        .and_then(|n| n.get_field("string"))
+                        .and_then(|s| <&str>::try_from(s.value).ok())
+                        .unwrap_or_default()
+                        .to_string(),
+                    f.get_field("method")
+                        .and_then(|m| m.get_field("modifiers"))
+                        .and_then(|m| <i32>::try_from(m.value).ok())
+                        .unwrap_or_default(),
+                )
+                .demangled(),
+            );
         }
 
-        Ok(StackTrace { frames })
+        Ok(StackTrace {
+            frames,
+            truncated: accessor
+                .get_field("truncated")
+                .and_then(|t| bool::try_from(t.value).ok())
+                .unwrap_or(false),
+        })
+    }
+
+    fn parse_class_name(key: &ConstantPoolKey, chunk: &Chunk) -> Result<String> {
+        let desc = ValueDescriptor::ConstantPool {
+            class_id: key.class_id,
+            constant_index: key.constant_pool_index,
+        };
+        let accessor = Accessor::new(chunk, &desc);
+
+        accessor
+            .get_field("name")
+            .and_then(|n| n.get_field("string"))
+            .and_then(|s| <&str>::try_from(s.value).ok())
+            .map(|s| s.to_string())
+            .ok_or_else(|| anyhow!("failed to get class name"))
     }
 
     fn parse_thread_state(key: &ConstantPoolKey, chunk: &Chunk) -> Result<String> {
@@ -281,30 +984,57 @@ impl Profile {
             .ok_or_else(|| anyhow!("failed to get thread state"))
     }
 
-    fn parse_thread(os_thread_id: i64, accessor: &Accessor<'_>) -> Result<Thread> {
-        let name = if let Some(name) = accessor
+    fn parse_thread_key(accessor: &Accessor<'_>) -> Result<ThreadKey> {
+        let os_thread_id = accessor
+            .get_field("osThreadId")
+            .and_then(|i| i64::try_from(i.value).ok())
+            .ok_or_else(|| anyhow!("Failed to get osThreadId"))?;
+        // javaThreadId is absent for non-Java threads
+        let java_thread_id = accessor
+            .get_field("javaThreadId")
+            .and_then(|i| i64::try_from(i.value).ok())
+            .unwrap_or(0);
+        Ok(ThreadKey {
+            java_thread_id,
+            os_thread_id,
+        })
+    }
+
+    fn parse_thread_name<'a>(accessor: &Accessor<'a>) -> Result<&'a str> {
+        accessor
             .get_field("javaName")
             .and_then(|n| <&str>::try_from(n.value).ok())
-        {
-            name.to_string()
-        } else if let Some(name) = accessor
-            .get_field("osName")
-            .and_then(|n| <&str>::try_from(n.value).ok())
-        {
-            name.to_string()
-        } else {
-            return Err(anyhow!("Failed to get thread name"));
-        };
-        Ok(Thread { os_thread_id, name })
+            .or_else(|| {
+                accessor
+                    .get_field("osName")
+                    .and_then(|n| <&str>::try_from(n.value).ok())
+            })
+            .ok_or_else(|| anyhow!("Failed to get thread name"))
     }
 }
 
+/// Convert ticks into nanoseconds.
+/// Calculated in i128 to be exact, since going through f64 rounds both the ticks beyond 2^53
+/// and the quotient.
+fn ticks_to_nanos(ticks: i64, ticks_per_second: i64) -> i64 {
+    (ticks as i128 * 1_000_000_000 / ticks_per_second.max(1) as i128) as i64
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::execution_sample::Profile;
+    use crate::execution_sample::{
+        ticks_to_nanos, Filter, LoadContext, Profile, ProfileLoader, ThreadKey,
+    };
+    use crate::flame_graph::FlameGraph;
+    use crate::profile::{
+        ConstantPoolKey, ContentionKind, ExecutionSample, SampleEventType, Thread, ThreadState,
+    };
+    use crate::test_util::{load, read};
+    use jfrs::reader::event::Accessor;
+    use jfrs::reader::JfrReader;
     use std::collections::HashSet;
     use std::fs::File;
-    use std::io::Read;
+    use std::io::{Cursor, Read};
     use std::path::PathBuf;
 
     #[test]
@@ -318,6 +1048,329 @@ mod tests {
         // these values can be checked by JMC
         assert_eq!(profile.threads.len(), 30);
         assert_eq!(profile.stack_trace_pool.len(), 25);
+        assert!(profile
+            .stack_trace_pool
+            .values()
+            .flat_map(|t| t.frames.iter())
+            .any(|f| f.descriptor.starts_with('(')));
+    }
+
+    #[test]
+    fn test_load_allocation_sample() {
+        let profile = load("hotspot-allocation.jfr");
+        // these values can be checked by `jfr print --json`.
+        // allocation events whose stack trace is disabled are skipped
+        let samples = profile
+            .per_thread_allocation_samples
+            .values()
+            .flatten()
+            .collect::<Vec<_>>();
+        assert_eq!(samples.len(), 227 + 205 + 64);
+        assert_eq!(samples.iter().filter(|s| s.outside_tlab).count(), 205);
+        // jdk.ObjectAllocationSample is sized by "weight", others by "allocationSize"
+        assert_eq!(
+            samples.iter().map(|s| s.allocation_size).sum::<i64>(),
+            857378480 + 834670160 + 4467176
+        );
+        assert!(samples.iter().all(|s| s
+            .object_class_key
+            .and_then(|k| profile.class_name_pool.get(&k))
+            .is_some()));
+
+        // the recording has allocation samples only, which still need the timeline width
+        assert!(profile.per_thread_samples.is_empty());
+        assert!(profile
+            .per_thread_allocation_samples
+            .values()
+            .all(|s| s.len() <= profile.column_count));
+    }
+
+    #[test]
+    fn test_load_contention_sample() {
+        let profile = load("hotspot-contention.jfr");
+        // these values can be checked by `jfr print --json`.
+        // contention events whose stack trace is disabled are skipped
+        let samples = profile
+            .per_thread_contention_samples
+            .values()
+            .flatten()
+            .collect::<Vec<_>>();
+        let duration_of = |kind| {
+            samples
+                .iter()
+                .filter(|s| s.kind == kind)
+                .map(|s| s.duration_nanos)
+                .sum::<i64>()
+        };
+        assert_eq!(samples.len(), 8 + 10);
+        assert_eq!(duration_of(ContentionKind::MonitorEnter), 90246754);
+        assert_eq!(duration_of(ContentionKind::ThreadPark), 78498645);
+        for sample in samples.iter() {
+            // threads are parked by LockSupport.parkNanos without a blocker
+            let class_name = sample
+                .monitor_class_key
+                .and_then(|k| profile.class_name_pool.get(&k));
+            // the monitor is held by "holder" while "waiter" tries to enter
+            let previous_owner = sample.previous_owner.and_then(|id| {
+                profile
+                    .threads
+                    .iter()
+                    .find(|t| t.java_thread_id == id)
+                    .map(|t| t.name.as_str())
+            });
+            match sample.kind {
+                ContentionKind::MonitorEnter => {
+                    assert_eq!(class_name.unwrap(), "java/lang/Object");
+                    assert_eq!(previous_owner, Some("holder"));
+                }
+                ContentionKind::ThreadPark => {
+                    assert!(class_name.is_none());
+                    assert!(sample.previous_owner.is_none());
+                }
+            }
+        }
+
+        // every thread row originates from the samples of the thread
+        for thread in profile.threads.iter() {
+            assert!(profile
+                .per_thread_contention_samples
+                .contains_key(&thread.id));
+        }
+    }
+
+    fn valid_sample_count(profile: &Profile) -> usize {
+        profile
+            .threads
+            .iter()
+            .flat_map(|t| {
+                profile
+                    .per_thread_samples
+                    .get(&t.id)
+                    .into_iter()
+                    .flatten()
+                    .filter(move |s| profile.is_valid_sample(t, *s))
+            })
+            .count()
+    }
+
+    #[test]
+    fn test_filter_event_type() {
+        let mut profile = load("profiler-wall.jfr");
+        // async-profiler records native frames in jdk.ExecutionSample as well
+        assert!(profile
+            .per_thread_samples
+            .values()
+            .flatten()
+            .all(|s| s.event_type == SampleEventType::ExecutionSample));
+
+        profile
+            .apply_filter(Filter {
+                event_type: Some(SampleEventType::NativeMethodSample),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(valid_sample_count(&profile), 0);
+    }
+
+    #[test]
+    fn test_filter_states() {
+        let mut profile = load("profiler-wall.jfr");
+        let runnable = profile
+            .per_thread_samples
+            .values()
+            .flatten()
+            .filter(|s| s.state == ThreadState::Runnable)
+            .count();
+        assert!(runnable > 0 && runnable < valid_sample_count(&profile));
+
+        profile
+            .apply_filter(Filter {
+                states: Some(vec![ThreadState::Runnable]),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(valid_sample_count(&profile), runnable);
+
+        profile
+            .apply_filter(Filter {
+                states: Some(vec![ThreadState::Runnable, ThreadState::Sleeping]),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(valid_sample_count(&profile), 8836);
+    }
+
+    #[test]
+    fn test_filter_time_range() {
+        let mut profile = load("profiler-wall.jfr");
+        let start_nanos = profile.interval.timestamp_at(0.25);
+        let end_nanos = profile.interval.timestamp_at(0.5);
+        let expected = profile
+            .per_thread_samples
+            .values()
+            .flatten()
+            .filter(|s| start_nanos <= s.timestamp_nanos && s.timestamp_nanos <= end_nanos)
+            .count();
+        assert!(expected > 0 && expected < 8836);
+
+        profile
+            .apply_filter(Filter {
+                start_nanos: Some(start_nanos),
+                end_nanos: Some(end_nanos),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(valid_sample_count(&profile), expected);
+        assert_eq!(
+            profile
+                .per_thread_samples
+                .values()
+                .map(|s| profile.samples_in_time_range(s).len())
+                .sum::<usize>(),
+            expected
+        );
+        assert_eq!(
+            FlameGraph::from_execution_sample(&profile, &Default::default())
+                .root
+                .total_count as usize,
+            expected
+        );
+
+        assert!(profile
+            .apply_filter(Filter {
+                start_nanos: Some(end_nanos),
+                end_nanos: Some(start_nanos),
+                ..Default::default()
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn test_filter_query() {
+        let mut profile = load("profiler-wall.jfr");
+        let expected = |f: &dyn Fn(&Thread, &ExecutionSample) -> bool| {
+            profile
+                .threads
+                .iter()
+                .flat_map(|t| {
+                    profile.per_thread_samples[&t.id]
+                        .iter()
+                        .filter(move |s| f(t, s))
+                })
+                .count()
+        };
+        let writer_runnable =
+            expected(&|t, s| t.name.starts_with("writer-") && s.state == ThreadState::Runnable);
+        let not_writer = expected(&|t, _| !t.name.starts_with("writer-"));
+        assert!(writer_runnable > 0 && not_writer > 0);
+
+        fn apply(profile: &mut Profile, query: &str) -> anyhow::Result<()> {
+            profile.apply_filter(Filter {
+                query: Some(query.to_string()),
+                ..Default::default()
+            })
+        }
+        apply(&mut profile, "thread:writer- AND state:RUNNABLE").unwrap();
+        assert_eq!(valid_sample_count(&profile), writer_runnable);
+        apply(&mut profile, "NOT thread:writer-").unwrap();
+        assert_eq!(valid_sample_count(&profile), not_writer);
+        apply(&mut profile, "thread:writer- OR NOT thread:writer-").unwrap();
+        assert_eq!(valid_sample_count(&profile), 8836);
+
+        let error = apply(&mut profile, "state:RUNNABLE AND").unwrap_err();
+        assert_eq!(error.to_string(), "Expected a predicate at position 18");
+    }
+
+    #[test]
+    fn test_filter_invalid() {
+        let mut profile = load("profiler-wall.jfr");
+        profile
+            .apply_filter(Filter {
+                thread_name_regex: Some("GC Thread".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        let expected = valid_sample_count(&profile);
+        let threads = profile.filtered_threads().len();
+
+        // the profile is left as it was even if some parts of the filter are valid
+        for invalid in [
+            Filter {
+                event_type: Some(SampleEventType::NativeMethodSample),
+                states: Some(vec![ThreadState::Sleeping]),
+                query: Some("thread:writer-".to_string()),
+                stack_trace_match_regex: Some("(".to_string()),
+                ..Default::default()
+            },
+            Filter {
+                thread_name_regex: Some("writer-".to_string()),
+                query: Some("state:RUNNABLE AND".to_string()),
+                ..Default::default()
+            },
+        ] {
+            assert!(profile.apply_filter(invalid).is_err());
+            assert_eq!(valid_sample_count(&profile), expected);
+            assert_eq!(profile.filtered_threads().len(), threads);
+            assert_eq!(
+                profile.filter().thread_name_regex.as_deref(),
+                Some("GC Thread")
+            );
+        }
+    }
+
+    #[test]
+    fn test_load_incrementally() {
+        let bytes = read("profiler-multichunk.jfr");
+
+        let mut loader = ProfileLoader::default();
+        // feed in pieces which aren't aligned to chunk boundaries
+        for piece in bytes.chunks(10_000) {
+            loader.feed_chunk(piece.to_vec()).unwrap();
+        }
+        let profile = loader.finish().unwrap();
+        assert_eq!(profile.threads.len(), 30);
+        assert_eq!(profile.stack_trace_pool.len(), 87);
+        assert_eq!(
+            profile
+                .per_thread_samples
+                .values()
+                .map(|s| s.len())
+                .sum::<usize>(),
+            2960 + 2960 + 2968
+        );
+        let chunks: HashSet<usize> = profile
+            .stack_trace_pool
+            .keys()
+            .map(|k| k.chunk_seq)
+            .collect();
+        assert_eq!(chunks.len(), 3);
+
+        let mut loader = ProfileLoader::default();
+        loader
+            .feed_chunk(bytes[..bytes.len() - 1].to_vec())
+            .unwrap();
+        assert!(loader.finish().is_err());
+
+        // non-JFR input fails on the first piece instead of being buffered
+        for (input, message) in [
+            (&b"FLX"[..], "Not a JFR recording"),
+            (
+                &[0x1f, 0x8b, 0x08][..],
+                "Recording must be decompressed first: Gzip",
+            ),
+        ] {
+            let mut loader = ProfileLoader::default();
+            let error = loader.feed_chunk(input.to_vec()).unwrap_err();
+            assert_eq!(error.to_string(), message);
+        }
+        // garbage after a valid chunk is rejected as well
+        let mut loader = ProfileLoader::default();
+        let mut garbage = bytes.clone();
+        garbage.extend_from_slice(b"garbage");
+        assert!(loader.feed_chunk(garbage).is_err());
+        // a prefix of the magic is just incomplete
+        let mut loader = ProfileLoader::default();
+        loader.feed_chunk(b"FL".to_vec()).unwrap();
     }
 
     #[test]
@@ -340,4 +1393,144 @@ mod tests {
             .collect();
         assert_eq!(chunks.len(), 3);
     }
+
+    #[test]
+    fn test_ticks_to_nanos() {
+        // 2^53 + 1 can't be represented in f64
+        assert_eq!(
+            ticks_to_nanos(9_007_199_254_740_993, 1_000_000_000),
+            9_007_199_254_740_993
+        );
+        // 30 days on a TSC whose frequency isn't a round number
+        let ticks_per_second = 2_899_999_993;
+        assert_eq!(
+            ticks_to_nanos(ticks_per_second * 2_592_000, ticks_per_second),
+            2_592_000_000_000_000
+        );
+        assert_eq!(
+            ticks_to_nanos(ticks_per_second - 1, ticks_per_second),
+            999_999_999
+        );
+        assert_eq!(
+            ticks_to_nanos(-ticks_per_second, ticks_per_second),
+            -1_000_000_000
+        );
+    }
+
+    #[test]
+    fn test_timestamps_multichunk() {
+        let bytes = read("profiler-multichunk.jfr");
+
+        // timestamps calculated from the raw ticks independently
+        let mut expected = vec![];
+        let mut reader = JfrReader::new(Cursor::new(bytes.clone()));
+        for (mut reader, chunk) in reader.chunks().flatten() {
+            let header = &chunk.header;
+            for event in reader.events(&chunk).flatten() {
+                if event.class.name() != "jdk.ExecutionSample" {
+                    continue;
+                }
+                let ticks = event
+                    .value()
+                    .get_field("startTime")
+                    .and_then(|Accessor { value, .. }| i64::try_from(value).ok())
+                    .unwrap();
+                let nanos = (ticks - header.start_ticks) as i128 * 1_000_000_000
+                    / header.ticks_per_second as i128;
+                expected.push(header.start_time_nanos + nanos as i64);
+            }
+        }
+        expected.sort_unstable();
+
+        let mut profile = Profile::default();
+        profile.load(bytes).unwrap();
+        let mut actual: Vec<i64> = profile
+            .per_thread_samples
+            .values()
+            .flatten()
+            .map(|s| s.timestamp_nanos)
+            .collect();
+        actual.sort_unstable();
+        assert_eq!(actual.len(), 2960 + 2960 + 2968);
+        assert_eq!(actual, expected);
+
+        // every sample should fall within the chunk it belongs to
+        assert!(actual.iter().all(|&t| profile
+            .summary
+            .chunks
+            .iter()
+            .any(|c| c.start_time_nanos <= t && t <= c.start_time_nanos + c.duration_nanos)));
+        assert_eq!(profile.interval.start_nanos, actual[0]);
+        assert_eq!(profile.interval.end_nanos, actual[actual.len() - 1]);
+        assert_eq!(profile.interval.elapsed_ratio(actual[0]), 0.0);
+        assert_eq!(
+            profile.interval.elapsed_ratio(actual[actual.len() - 1]),
+            1.0
+        );
+    }
+
+    fn sample(timestamp_nanos: i64) -> ExecutionSample {
+        ExecutionSample {
+            timestamp_nanos,
+            event_type: SampleEventType::ExecutionSample,
+            state: ThreadState::Runnable,
+            stack_trace_key: ConstantPoolKey::new(0, 0, 1),
+        }
+    }
+
+    #[test]
+    fn test_reused_os_thread_id() {
+        // worker-2 was started after worker-1 ended, on the same OS thread id
+        let profile = load("hotspot-threads.jfr");
+        let workers = profile
+            .threads
+            .iter()
+            .filter(|t| t.name.starts_with("worker-"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            workers.iter().map(|t| t.name.as_str()).collect::<Vec<_>>(),
+            vec!["worker-1", "worker-2"]
+        );
+        assert_eq!(workers[0].os_thread_id, workers[1].os_thread_id);
+        assert_ne!(workers[0].java_thread_id, workers[1].java_thread_id);
+        for thread in workers {
+            assert!(!profile.per_thread_samples[&thread.id].is_empty());
+        }
+    }
+
+    #[test]
+    fn test_thread_lifetime() {
+        let key = ThreadKey {
+            java_thread_id: 0,
+            os_thread_id: 42,
+        };
+        let mut ctx = LoadContext::default();
+        ctx.thread_pool.insert(
+            key,
+            vec![(10, "first".to_string()), (110, "second".to_string())],
+        );
+        // the first thread ended at 50 then the second started at 100
+        ctx.thread_boundaries.insert(key, vec![100, 51]);
+        ctx.per_thread_samples
+            .insert(key, vec![sample(120), sample(10), sample(110), sample(20)]);
+
+        let table = ctx.build_threads();
+        assert_eq!(
+            table
+                .threads
+                .iter()
+                .map(|t| t.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["first", "second"]
+        );
+        for (thread, timestamps) in table.threads.iter().zip([[10, 20], [110, 120]]) {
+            assert_eq!(
+                table.per_thread_samples[&thread.id]
+                    .iter()
+                    .map(|s| s.timestamp_nanos)
+                    .collect::<Vec<_>>(),
+                timestamps
+            );
+        }
+    }
 }
@@ -0,0 +1,524 @@
+//! A small boolean query language to filter samples, e.g.
+//! `contains:KafkaApis.handle AND NOT contains:FileChannel AND state:RUNNABLE`.
+//!
+//! Predicates are written as `name:value`, where the value is either a bare word or
+//! a double-quoted string (`\"` and `\\` can be escaped in it):
+//! - `contains:TEXT` - any frame's name contains the text
+//! - `root:TEXT` - the root frame's name contains the text. Never matches truncated stacks
+//! - `leaf:TEXT` - the leaf frame's name contains the text
+//! - `thread:TEXT` - the thread name contains the text
+//! - `state:STATE` - the thread state is STATE, e.g. `RUNNABLE`, `STATE_PARKED` or `TimedWaiting`.
+//!   Never matches allocation and contention samples, which don't have the thread state
+//! - `type:TYPE` - any frame is of the type, e.g. `Native` or `JitCompiled`
+//!
+//! Predicates can be combined with `NOT`, `AND` and `OR` (in the order of precedence)
+//! and grouped by parentheses.
+//! Should not contain any wasm dependencies.
+
+use crate::profile::{FrameType, StackTrace, Thread, ThreadState};
+use std::fmt::{Display, Formatter};
+
+/// Error on parsing a query. The position is the byte offset in the query
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    fn new(position: usize, message: impl Into<String>) -> Self {
+        Self {
+            position,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Predicate {
+    Contains(String),
+    Root(String),
+    Leaf(String),
+    Thread(String),
+    State(ThreadState),
+    FrameType(FrameType),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Predicate(Predicate),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+/// Compiled query
+#[derive(Clone, Debug)]
+pub struct Query {
+    expr: Expr,
+}
+
+impl Query {
+    pub fn parse(query: &str) -> Result<Self, ParseError> {
+        let tokens = tokenize(query)?;
+        let mut parser = Parser {
+            tokens,
+            idx: 0,
+            end: query.len(),
+        };
+        let expr = parser.parse_or()?;
+        match parser.peek() {
+            None => Ok(Self { expr }),
+            Some((token, position)) => Err(ParseError::new(
+                position,
+                format!("Unexpected {}", token.describe()),
+            )),
+        }
+    }
+
+    /// Whether the sample of the stack trace taken on the thread in the state matches the query.
+    /// The state is None for the samples which don't have the thread state
+    pub fn matches(
+        &self,
+        stack_trace: &StackTrace,
+        thread: &Thread,
+        state: Option<ThreadState>,
+    ) -> bool {
+        Self::eval(&self.expr, stack_trace, thread, state)
+    }
+
+    fn eval(
+        expr: &Expr,
+        stack_trace: &StackTrace,
+        thread: &Thread,
+        state: Option<ThreadState>,
+    ) -> bool {
+        match expr {
+            Expr::Predicate(predicate) => match predicate {
+                Predicate::Contains(text) => stack_trace
+                    .frames
+                    .iter()
+                    .any(|f| f.name().contains(text.as_str())),
+                // frames are ordered from the leaf to the root
+                Predicate::Root(text) => {
+                    !stack_trace.truncated
+                        && stack_trace
+                            .frames
+                            .last()
+                            .map(|f| f.name().contains(text.as_str()))
+                            .unwrap_or(false)
+                }
+                Predicate::Leaf(text) => stack_trace
+                    .frames
+                    .first()
+                    .map(|f| f.name().contains(text.as_str()))
+                    .unwrap_or(false),
+                Predicate::Thread(text) => thread.name.contains(text.as_str()),
+                Predicate::State(s) => state == Some(*s),
+                Predicate::FrameType(t) => stack_trace.frames.iter().any(|f| f.frame_type == *t),
+            },
+            Expr::Not(e) => !Self::eval(e, stack_trace, thread, state),
+            Expr::And(l, r) => {
+                Self::eval(l, stack_trace, thread, state)
+                    && Self::eval(r, stack_trace, thread, state)
+            }
+            Expr::Or(l, r) => {
+                Self::eval(l, stack_trace, thread, state)
+                    || Self::eval(r, stack_trace, thread, state)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+    Predicate(Predicate),
+}
+
+impl Token {
+    fn describe(&self) -> &'static str {
+        match self {
+            Token::LParen => "'('",
+            Token::RParen => "')'",
+            Token::And => "AND",
+            Token::Or => "OR",
+            Token::Not => "NOT",
+            Token::Predicate(_) => "predicate",
+        }
+    }
+}
+
+fn tokenize(query: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let mut tokens = vec![];
+    let mut chars = query.char_indices().peekable();
+    while let Some(&(position, c)) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push((Token::LParen, position));
+            }
+            ')' => {
+                chars.next();
+                tokens.push((Token::RParen, position));
+            }
+            c if is_word_char(c) => {
+                let mut word = String::new();
+                while let Some(&(_, c)) = chars.peek() {
+                    if !is_word_char(c) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                if let Some(&(value_position, ':')) = chars.peek() {
+                    chars.next();
+                    let value_position = value_position + 1;
+                    let value = read_value(query, &mut chars, value_position)?;
+                    let predicate = predicate(word.as_str(), value, position, value_position)?;
+                    tokens.push((Token::Predicate(predicate), position));
+                    continue;
+                }
+                let token = match word.to_ascii_uppercase().as_str() {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "NOT" => Token::Not,
+                    _ => {
+                        return Err(ParseError::new(
+                            position,
+                            format!("Unknown keyword '{}'", word),
+                        ))
+                    }
+                };
+                tokens.push((token, position));
+            }
+            _ => {
+                return Err(ParseError::new(
+                    position,
+                    format!("Unexpected character '{}'", c),
+                ))
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+/// Read the value of the predicate, which is either a quoted string or a bare word
+fn read_value(
+    query: &str,
+    chars: &mut std::iter::Peekable<std::str::CharIndices<'_>>,
+    position: usize,
+) -> Result<String, ParseError> {
+    let mut value = String::new();
+    match chars.peek() {
+        Some(&(_, '"')) => {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some((_, '"')) => break,
+                    Some((escape_position, '\\')) => match chars.next() {
+                        Some((_, c @ ('"' | '\\'))) => value.push(c),
+                        _ => {
+                            return Err(ParseError::new(escape_position, "Invalid escape sequence"))
+                        }
+                    },
+                    Some((_, c)) => value.push(c),
+                    None => return Err(ParseError::new(position, "Unterminated string")),
+                }
+            }
+        }
+        _ => {
+            while let Some(&(_, c)) = chars.peek() {
+                if c.is_whitespace() || c == '(' || c == ')' {
+                    break;
+                }
+                value.push(c);
+                chars.next();
+            }
+        }
+    }
+    if value.is_empty() {
+        let position = chars.peek().map(|(p, _)| *p).unwrap_or(query.len());
+        return Err(ParseError::new(position, "Expected a value"));
+    }
+    Ok(value)
+}
+
+fn predicate(
+    name: &str,
+    value: String,
+    position: usize,
+    value_position: usize,
+) -> Result<Predicate, ParseError> {
+    match name {
+        "contains" => Ok(Predicate::Contains(value)),
+        "root" => Ok(Predicate::Root(value)),
+        "leaf" => Ok(Predicate::Leaf(value)),
+        "thread" => Ok(Predicate::Thread(value)),
+        "state" => {
+            let normalized = normalize(value.trim_start_matches("STATE_"));
+            ThreadState::ALL
+                .into_iter()
+                .find(|s| normalize(format!("{:?}", s).as_str()) == normalized)
+                .map(Predicate::State)
+                .ok_or_else(|| {
+                    ParseError::new(value_position, format!("Unknown thread state '{}'", value))
+                })
+        }
+        "type" => {
+            let normalized = normalize(value.as_str());
+            [
+                FrameType::Interpreted,
+                FrameType::JitCompiled,
+                FrameType::Inlined,
+                FrameType::Native,
+                FrameType::Cpp,
+                FrameType::Kernel,
+                FrameType::C1Compiled,
+                FrameType::Unknown,
+            ]
+            .into_iter()
+            .find(|t| normalize(format!("{:?}", t).as_str()) == normalized)
+            .map(Predicate::FrameType)
+            .ok_or_else(|| {
+                ParseError::new(value_position, format!("Unknown frame type '{}'", value))
+            })
+        }
+        _ => Err(ParseError::new(
+            position,
+            format!("Unknown predicate '{}'", name),
+        )),
+    }
+}
+
+/// Make the name comparable regardless of the case and underscores,
+/// so that e.g. `TIMED_WAITING` matches `TimedWaiting`
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| *c != '_')
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    idx: usize,
+    /// The position of the end of the query
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<(&Token, usize)> {
+        self.tokens.get(self.idx).map(|(t, p)| (t, *p))
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.parse_and()?;
+        while let Some((Token::Or, _)) = self.peek() {
+            self.idx += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.parse_not()?;
+        while let Some((Token::And, _)) = self.peek() {
+            self.idx += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_not()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, ParseError> {
+        if let Some((Token::Not, _)) = self.peek() {
+            self.idx += 1;
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ParseError> {
+        match self.peek() {
+            Some((Token::Predicate(predicate), _)) => {
+                let expr = Expr::Predicate(predicate.clone());
+                self.idx += 1;
+                Ok(expr)
+            }
+            Some((Token::LParen, position)) => {
+                self.idx += 1;
+                let expr = self.parse_or()?;
+                match self.peek() {
+                    Some((Token::RParen, _)) => {
+                        self.idx += 1;
+                        Ok(expr)
+                    }
+                    Some((token, position)) => Err(ParseError::new(
+                        position,
+                        format!("Expected ')' but got {}", token.describe()),
+                    )),
+                    None => Err(ParseError::new(
+                        self.end,
+                        format!("Unclosed '(' at position {}", position),
+                    )),
+                }
+            }
+            Some((token, position)) => Err(ParseError::new(
+                position,
+                format!("Expected a predicate but got {}", token.describe()),
+            )),
+            None => Err(ParseError::new(self.end, "Expected a predicate")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::execution_sample::query::{ParseError, Query};
+    use crate::profile::{FrameType, StackFrame, StackTrace, Thread, ThreadState};
+
+    fn trace(truncated: bool) -> StackTrace {
+        let frame = |type_name: &str, method_name: &str, frame_type| {
+            StackFrame::new(
+                type_name.to_string(),
+                method_name.to_string(),
+                frame_type,
+                0,
+            )
+        };
+        StackTrace {
+            frames: vec![
+                frame("", "pread64", FrameType::Native),
+                frame("sun/nio/ch/FileChannelImpl", "read", FrameType::JitCompiled),
+                frame("kafka/server/KafkaApis", "handle", FrameType::Inlined),
+                frame("java/lang/Thread", "run", FrameType::Interpreted),
+            ],
+            truncated,
+        }
+    }
+
+    fn thread(name: &str) -> Thread {
+        Thread {
+            id: 1,
+            os_thread_id: 1,
+            java_thread_id: 1,
+            name: name.to_string(),
+        }
+    }
+
+    fn matches(query: &str) -> bool {
+        Query::parse(query).unwrap().matches(
+            &trace(false),
+            &thread("kafka-request-handler-0"),
+            Some(ThreadState::Runnable),
+        )
+    }
+
+    #[test]
+    fn test_predicates() {
+        assert!(matches("contains:KafkaApis.handle"));
+        assert!(!matches("contains:KafkaProducer"));
+        assert!(matches("root:java/lang/Thread.run"));
+        assert!(!matches("root:KafkaApis"));
+        assert!(matches("leaf:pread64"));
+        assert!(!matches("leaf:FileChannelImpl"));
+        assert!(matches("thread:request-handler"));
+        assert!(!matches("thread:network-thread"));
+        assert!(matches("state:RUNNABLE"));
+        assert!(matches("state:STATE_RUNNABLE"));
+        assert!(matches("state:runnable"));
+        assert!(!matches("state:TIMED_WAITING"));
+        assert!(matches("type:Native"));
+        assert!(matches("type:jit_compiled"));
+        assert!(!matches("type:Kernel"));
+        assert!(matches(r#"contains:"FileChannelImpl.read""#));
+
+        let query = Query::parse("root:Thread.run").unwrap();
+        assert!(!query.matches(&trace(true), &thread("main"), Some(ThreadState::Runnable)));
+
+        // samples without the thread state, e.g. allocation samples
+        let query = Query::parse("thread:main AND NOT state:RUNNABLE").unwrap();
+        assert!(query.matches(&trace(false), &thread("main"), None));
+        let query = Query::parse("thread:main AND state:RUNNABLE").unwrap();
+        assert!(!query.matches(&trace(false), &thread("main"), None));
+    }
+
+    #[test]
+    fn test_operators() {
+        assert!(matches(
+            "contains:KafkaApis.handle AND NOT contains:FileChannel OR state:RUNNABLE"
+        ));
+        assert!(!matches(
+            "contains:KafkaApis.handle AND NOT (contains:FileChannel OR state:RUNNABLE)"
+        ));
+        assert!(matches("NOT NOT leaf:pread64"));
+        assert!(matches("type:Kernel or thread:kafka and not state:parked"));
+        // AND binds tighter than OR
+        assert!(matches("leaf:pread64 OR leaf:foo AND leaf:bar"));
+        assert!(!matches("(leaf:pread64 OR leaf:foo) AND leaf:bar"));
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let error = |query: &str| Query::parse(query).unwrap_err();
+        assert_eq!(
+            error("contains:Foo AND"),
+            ParseError::new(16, "Expected a predicate")
+        );
+        assert_eq!(
+            error("contains:Foo XOR leaf:Bar"),
+            ParseError::new(13, "Unknown keyword 'XOR'")
+        );
+        assert_eq!(
+            error("(contains:Foo OR leaf:Bar"),
+            ParseError::new(25, "Unclosed '(' at position 0")
+        );
+        assert_eq!(
+            error("contains:Foo leaf:Bar"),
+            ParseError::new(13, "Unexpected predicate")
+        );
+        assert_eq!(
+            error("method:Foo"),
+            ParseError::new(0, "Unknown predicate 'method'")
+        );
+        assert_eq!(
+            error("leaf:Foo AND state:SLEEPY"),
+            ParseError::new(19, "Unknown thread state 'SLEEPY'")
+        );
+        assert_eq!(
+            error(r#"contains:"Foo"#),
+            ParseError::new(9, "Unterminated string")
+        );
+        assert_eq!(
+            error("contains: Foo"),
+            ParseError::new(9, "Expected a value")
+        );
+        assert_eq!(
+            error("leaf:Foo & leaf:Bar"),
+            ParseError::new(9, "Unexpected character '&'")
+        );
+        assert_eq!(
+            error("leaf:Foo AND state:SLEEPY").to_string(),
+            "Unknown thread state 'SLEEPY' at position 19"
+        );
+    }
+}
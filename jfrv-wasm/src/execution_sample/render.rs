@@ -1,16 +1,22 @@
 //! Execution sample chart renderer.
 
-use crate::execution_sample::{Filter, Profile};
-use crate::profile::{StackTrace, ThreadState};
+use crate::encoding::{self, Encoding};
+use crate::execution_sample::group::{self, Row, ThreadGroupConfig};
+use crate::execution_sample::summary::RecordingSummary;
+use crate::execution_sample::{Filter, Profile, ProfileLoader};
+use crate::profile::{
+    ContentionKind, ExecutionSample, SampleEventType, StackTrace, Thread, ThreadState,
+};
+use crate::time::{TimeDisplayConfig, TimeFormatter};
 use crate::web::{Canvas, Document, Svg};
 use crate::Result;
-use crate::{flame_graph, Dimension};
-use chrono::{Local, NaiveDateTime, TimeZone};
-use flate2::read::GzDecoder;
+use crate::{flame_graph, jfr, pprof, speedscope, trace_event, Dimension};
 use log::debug;
-use std::io::{Cursor, Read};
+use rustc_hash::FxHashSet;
+use std::collections::HashMap;
 
 use crate::flame_graph::render::{FlameGraph, FlameGraphConfig};
+use crate::flame_graph::{AggregationConfig, AllocationWeight};
 use serde::{Deserialize, Serialize};
 use tsify::Tsify;
 use wasm_bindgen::prelude::*;
@@ -25,6 +31,7 @@ pub struct ChartConfig {
     pub header_config: HeaderConfig,
     pub sample_view_config: SampleViewConfig,
     pub thread_state_color_config: ThreadStateColorConfig,
+    pub contention_color_config: ContentionColorConfig,
     pub overlay_config: OverlayConfig,
     pub axis_config: AxisConfig,
 }
@@ -71,10 +78,29 @@ pub struct AxisConfig {
 #[tsify(into_wasm_abi, from_wasm_abi)]
 #[serde(rename_all = "camelCase")]
 pub struct ThreadStateColorConfig {
-    pub state_runnable_rgb_hex: u32,
-    pub state_sleeping_rgb_hex: u32,
-    pub state_unknown_rgb_hex: u32,
+    /// Colors of the states. Falls back to the color of Unknown if absent
+    pub state_rgb_hex: HashMap<ThreadState, u32>,
     pub state_hidden_rgb_hex: u32,
+    /// Color for samples from jdk.NativeMethodSample regardless of the thread state
+    pub native_method_sample_rgb_hex: u32,
+}
+
+impl ThreadStateColorConfig {
+    fn state_color(&self, state: ThreadState) -> u32 {
+        self.state_rgb_hex
+            .get(&state)
+            .or_else(|| self.state_rgb_hex.get(&ThreadState::Unknown))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Default, Deserialize, Serialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct ContentionColorConfig {
+    pub monitor_enter_rgb_hex: u32,
+    pub thread_park_rgb_hex: u32,
 }
 
 #[derive(Default, Deserialize, Serialize, Tsify)]
@@ -82,28 +108,59 @@ pub struct ThreadStateColorConfig {
 #[serde(rename_all = "camelCase")]
 pub struct ExecutionSampleInfo {
     pub timestamp: String,
+    /// Absent for contention samples
+    pub event_type: Option<SampleEventType>,
+    /// Absent for contention samples
+    pub state: Option<ThreadState>,
     pub stack_trace: StackTrace,
+    /// Human-readable method declaration of each frame
+    pub frame_signatures: Vec<String>,
     pub os_thread_id: String,
+    /// Present only for contention samples
+    pub contention: Option<ContentionInfo>,
 }
 
-/// Conditions to filter data
 #[derive(Deserialize, Serialize, Tsify)]
 #[tsify(into_wasm_abi, from_wasm_abi)]
-pub enum Encoding {
-    Uncompressed,
-    Gzip,
+#[serde(rename_all = "camelCase")]
+pub struct ContentionInfo {
+    pub kind: ContentionKind,
+    pub duration: String,
+    pub monitor_class: Option<String>,
+    /// Name of the thread which held the monitor last
+    pub previous_owner: Option<String>,
+}
+
+#[derive(Default, Deserialize, Serialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchiveEntries {
+    pub names: Vec<String>,
 }
 
 /// State of the current rendered chart
 #[derive(Default)]
 pub struct ChartState {
+    highlighted_row_idx: Option<usize>,
     highlighted_thread_id: Option<i64>,
-    highlighted_sample_idx: Option<usize>,
+    highlighted_sample: Option<HighlightedSample>,
+}
+
+/// Index of the sample under the mouse in the samples of the thread
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum HighlightedSample {
+    Execution(usize),
+    Contention(usize),
 }
 
 #[wasm_bindgen]
 pub struct Renderer {
     profile: Profile,
+    loader: Option<ProfileLoader>,
+    thread_group_config: ThreadGroupConfig,
+    time_formatter: TimeFormatter,
+    expanded_groups: FxHashSet<String>,
+    rows: Vec<Row>,
     chart_config: ChartConfig,
     chart_state: ChartState,
     document: Document,
@@ -125,6 +182,11 @@ impl Renderer {
         let document = Document::try_new()?;
         Ok(Self {
             profile: Profile::default(),
+            loader: None,
+            thread_group_config: ThreadGroupConfig::default(),
+            time_formatter: TimeFormatter::default(),
+            expanded_groups: FxHashSet::default(),
+            rows: vec![],
             chart_state: ChartState::default(),
             header: document.get_svg_by_id(chart_config.header_config.element_id.as_str())?,
             header_overlay: document
@@ -153,25 +215,74 @@ impl Renderer {
     }
 
     pub fn initialize(&mut self, bytes: Vec<u8>, encoding: Encoding) -> Result<()> {
-        match encoding {
-            Encoding::Uncompressed => {
-                self.profile.load(bytes).map_err(Self::map_js_value)?;
-            }
-            Encoding::Gzip => {
-                let mut decoded = Vec::new();
-                GzDecoder::new(Cursor::new(bytes))
-                    .read_to_end(&mut decoded)
-                    .map_err(Self::map_js_value)?;
-                self.profile.load(decoded).map_err(Self::map_js_value)?;
-            }
-        }
-        Ok(())
+        let decoded = encoding::decode(bytes, encoding, None).map_err(Self::map_js_value)?;
+        self.profile.load(decoded).map_err(Self::map_js_value)?;
+        self.refresh_rows()
+    }
+
+    /// Load the recording stored as the entry of the zip archive
+    pub fn initialize_archive_entry(&mut self, bytes: Vec<u8>, entry_name: String) -> Result<()> {
+        let decoded = encoding::decode(bytes, Encoding::Zip, Some(entry_name.as_str()))
+            .map_err(Self::map_js_value)?;
+        self.profile.load(decoded).map_err(Self::map_js_value)?;
+        self.refresh_rows()
+    }
+
+    /// Start loading an uncompressed recording incrementally.
+    /// Pieces of the file should be passed by feed_chunk then finish_loading should be called.
+    pub fn start_loading(&mut self) {
+        self.loader = Some(ProfileLoader::default());
+    }
+
+    pub fn feed_chunk(&mut self, bytes: Vec<u8>) -> Result<()> {
+        self.loader
+            .as_mut()
+            .ok_or_else(|| JsValue::from_str("Loading is not started"))?
+            .feed_chunk(bytes)
+            .map_err(Self::map_js_value)
+    }
+
+    pub fn finish_loading(&mut self) -> Result<()> {
+        self.profile = self
+            .loader
+            .take()
+            .ok_or_else(|| JsValue::from_str("Loading is not started"))?
+            .finish()
+            .map_err(Self::map_js_value)?;
+        self.refresh_rows()
+    }
+
+    /// Restore the session from the snapshot taken by `snapshot`
+    pub fn restore(&mut self, bytes: Vec<u8>) -> Result<()> {
+        self.profile = Profile::restore(bytes.as_slice()).map_err(Self::map_js_value)?;
+        self.refresh_rows()
+    }
+
+    /// Snapshot of the loaded profile along with the current filter
+    pub fn snapshot(&self) -> Result<Vec<u8>> {
+        self.profile.snapshot().map_err(Self::map_js_value)
+    }
+
+    /// The filter which is currently applied, e.g. to fill the inputs after restoring a session
+    pub fn filter(&self) -> Filter {
+        self.profile.filter().clone()
+    }
+
+    pub fn summary(&self) -> RecordingSummary {
+        self.profile.summary.clone()
+    }
+
+    /// List the files in the zip archive so that the caller can pick one of them
+    pub fn archive_entries(bytes: &[u8]) -> Result<ArchiveEntries> {
+        Ok(ArchiveEntries {
+            names: encoding::archive_entries(bytes).map_err(Self::map_js_value)?,
+        })
     }
 
     pub fn render(&self) -> Result<()> {
         let document = &self.document;
 
-        let chart_height = self.row_height() * self.profile.filtered_threads().len() as f32;
+        let chart_height = self.row_height() * self.rows.len() as f32;
         debug!("start render");
 
         self.header.clear();
@@ -180,65 +291,47 @@ impl Renderer {
         self.chart.raw.set_height(chart_height as u32);
         debug!("start draw frame");
 
-        for (i, thread) in self.profile.filtered_threads().iter().enumerate() {
-            if let Some(samples) = self.profile.per_thread_samples.get(&thread.os_thread_id) {
-                let y = self.row_height() * i as f32
-                    + (self.row_height()
-                        - self
-                            .chart_config
-                            .sample_view_config
-                            .sample_render_size
-                            .height)
-                        / 2.0;
-                for (_j, sample) in samples.iter().enumerate() {
-                    let x = self.sample_view_width() * self.elapsed_ratio(sample.timestamp_nanos);
-                    let color = if self.profile.is_valid_sample(sample) {
-                        match sample.state {
-                            ThreadState::Unknown => {
-                                self.chart_config
-                                    .thread_state_color_config
-                                    .state_unknown_rgb_hex
-                            }
-                            ThreadState::Runnable => {
-                                self.chart_config
-                                    .thread_state_color_config
-                                    .state_runnable_rgb_hex
-                            }
-                            ThreadState::Sleeping => {
-                                self.chart_config
-                                    .thread_state_color_config
-                                    .state_sleeping_rgb_hex
-                            }
-                        }
-                    } else {
-                        self.chart_config
-                            .thread_state_color_config
-                            .state_hidden_rgb_hex
-                    };
-
-                    self.chart
-                        .ctx
-                        .set_fill_style(&JsValue::from_str(format!("#{:x}", color).as_str()));
-                    self.chart.ctx.fill_rect(
-                        x as f64,
-                        y as f64,
-                        self.chart_config
-                            .sample_view_config
-                            .sample_render_size
-                            .width as f64,
-                        self.chart_config
-                            .sample_view_config
-                            .sample_render_size
-                            .height as f64,
-                    );
+        let threads = self.profile.filtered_threads();
+        for (i, row) in self.rows.iter().enumerate() {
+            let y = self.row_height() * i as f32
+                + (self.row_height()
+                    - self
+                        .chart_config
+                        .sample_view_config
+                        .sample_render_size
+                        .height)
+                    / 2.0;
+            let label = match row {
+                Row::Thread(idx) => {
+                    let thread = &threads[*idx];
+                    self.render_thread(thread, y);
+                    let indent = if self.is_group_member(i) { "    " } else { "" };
+                    format!(
+                        "{}{} [tid=0x{:x}]",
+                        indent, thread.name, thread.os_thread_id
+                    )
                 }
-            }
+                Row::Group {
+                    name,
+                    thread_indices,
+                    expanded,
+                } => {
+                    let members: Vec<&Thread> =
+                        thread_indices.iter().map(|idx| &threads[*idx]).collect();
+                    self.render_group(&members, y);
+                    format!(
+                        "{} {} ({} threads)",
+                        if *expanded { "\u{25be}" } else { "\u{25b8}" },
+                        name,
+                        members.len()
+                    )
+                }
+            };
 
             let text = document
                 .raw
                 .create_element_ns(Some("http://www.w3.org/2000/svg"), "text")?;
-            let thread_name = format!("{} [tid=0x{:x}]", thread.name, thread.os_thread_id);
-            let text_node = document.raw.create_text_node(&thread_name);
+            let text_node = document.raw.create_text_node(&label);
             text.set_attribute(
                 "x",
                 (self.chart_config.default_margin * 2.0)
@@ -264,7 +357,7 @@ impl Renderer {
 
         debug!("start render border");
         // render borders based on the header width retrieved from bbox
-        for i in 0..(self.profile.filtered_threads().len() as isize - 1) {
+        for i in 0..(self.rows.len() as isize - 1) {
             let y = (self.row_height() + self.row_height() * i as f32).to_string();
             let line = document
                 .raw
@@ -297,7 +390,56 @@ impl Renderer {
 
     pub fn flame_graph(&mut self, config: FlameGraphConfig) -> FlameGraph {
         FlameGraph::from(
-            &flame_graph::FlameGraph::from_execution_sample(&self.profile),
+            &flame_graph::FlameGraph::from_execution_sample(&self.profile, &config.aggregation),
+            &config.color_palette,
+        )
+    }
+
+    /// Folded stacks (`frame;frame;frame count` lines) of the samples passing the current filter
+    pub fn collapsed_stacks(&self, config: AggregationConfig) -> String {
+        flame_graph::FlameGraph::from_execution_sample(&self.profile, &config).to_folded()
+    }
+
+    /// Gzipped pprof profile.proto of the samples passing the current filter
+    pub fn pprof(&self, config: AggregationConfig) -> Result<Vec<u8>> {
+        pprof::export(&self.profile, &config).map_err(Self::map_js_value)
+    }
+
+    /// speedscope JSON of the samples passing the current filter
+    pub fn speedscope(&self, name: &str, config: AggregationConfig) -> Result<String> {
+        speedscope::export(&self.profile, name, &config).map_err(Self::map_js_value)
+    }
+
+    /// Chrome trace events JSON of the samples passing the current filter
+    pub fn trace_events(&self, config: AggregationConfig) -> Result<String> {
+        trace_event::export_execution_sample(&self.profile, &config).map_err(Self::map_js_value)
+    }
+
+    /// JFR recording of the samples passing the current filter.
+    /// Unlike other formats, the aggregation config isn't applied since
+    /// the recording keeps the original frames to be loaded again
+    pub fn jfr(&self) -> Result<Vec<u8>> {
+        jfr::write(&self.profile).map_err(Self::map_js_value)
+    }
+
+    pub fn allocation_flame_graph(
+        &mut self,
+        config: FlameGraphConfig,
+        weight: AllocationWeight,
+    ) -> FlameGraph {
+        FlameGraph::from(
+            &flame_graph::FlameGraph::from_allocation_sample(
+                &self.profile,
+                weight,
+                &config.aggregation,
+            ),
+            &config.color_palette,
+        )
+    }
+
+    pub fn contention_flame_graph(&mut self, config: FlameGraphConfig) -> FlameGraph {
+        FlameGraph::from(
+            &flame_graph::FlameGraph::from_contention_sample(&self.profile, &config.aggregation),
             &config.color_palette,
         )
     }
@@ -306,6 +448,63 @@ impl Renderer {
         self.profile
             .apply_filter(filter)
             .map_err(Self::map_js_value)?;
+        self.refresh_rows()?;
+        self.render()
+    }
+
+    /// Narrow the filter down to the time range between the x coordinates on the chart,
+    /// e.g. selected by dragging
+    pub fn apply_time_range(&mut self, start_x: f32, end_x: f32) -> Result<()> {
+        let mut filter = self.profile.filter().clone();
+        filter.start_nanos = Some(self.timestamp_at(start_x.min(end_x)));
+        filter.end_nanos = Some(self.timestamp_at(start_x.max(end_x)));
+        self.apply_filter(filter)
+    }
+
+    pub fn clear_time_range(&mut self) -> Result<()> {
+        let mut filter = self.profile.filter().clone();
+        filter.start_nanos = None;
+        filter.end_nanos = None;
+        self.apply_filter(filter)
+    }
+
+    /// Group threads into rows by the normalized name
+    pub fn apply_thread_group(&mut self, config: ThreadGroupConfig) -> Result<()> {
+        self.thread_group_config = config;
+        self.expanded_groups.clear();
+        self.refresh_rows()?;
+        self.render()
+    }
+
+    /// Change how timestamps are displayed
+    pub fn apply_time_display(&mut self, config: TimeDisplayConfig) -> Result<()> {
+        self.time_formatter = TimeFormatter::new(&config).map_err(Self::map_js_value)?;
+        Ok(())
+    }
+
+    /// Format the timestamp according to the time display config
+    pub fn format_timestamp(&self, timestamp_nanos: i64, fraction_digits: u32) -> String {
+        self.time_formatter.format(
+            timestamp_nanos,
+            self.profile.interval.start_nanos,
+            fraction_digits,
+        )
+    }
+
+    /// Expand or collapse the group row under the mouse
+    pub fn on_header_click(&mut self) -> Result<()> {
+        let name = match self
+            .chart_state
+            .highlighted_row_idx
+            .and_then(|i| self.rows.get(i))
+        {
+            Some(Row::Group { name, .. }) => name.clone(),
+            _ => return Ok(()),
+        };
+        if !self.expanded_groups.remove(&name) {
+            self.expanded_groups.insert(name);
+        }
+        self.refresh_rows()?;
         self.render()
     }
 
@@ -328,48 +527,77 @@ impl Renderer {
     pub fn on_mouse_out(&mut self) -> Result<()> {
         self.header_overlay.clear();
         self.chart_overlay.clear();
+        self.chart_state.highlighted_row_idx = None;
         self.chart_state.highlighted_thread_id = None;
-        self.chart_state.highlighted_sample_idx = None;
+        self.chart_state.highlighted_sample = None;
         self.time_label.style().set_property("display", "none")
     }
 
     pub fn on_chart_click(&self) -> Option<ExecutionSampleInfo> {
-        match self.chart_state {
-            ChartState {
-                highlighted_thread_id: Some(thread_id),
-                highlighted_sample_idx: Some(sample_idx),
-            } => self
-                .profile
-                .per_thread_samples
-                .get(&thread_id)
-                .and_then(|s| s.get(sample_idx))
-                .and_then(|s| {
-                    let stack_trace = self.profile.stack_trace_pool.get(&s.stack_trace_key);
-                    let timestamp = NaiveDateTime::from_timestamp(
-                        s.timestamp_nanos / 1_000_000_000,
-                        (s.timestamp_nanos % 1_000_000_000) as u32,
-                    );
-                    stack_trace.map(|t| ExecutionSampleInfo {
-                        timestamp: Local
-                            .from_utc_datetime(&timestamp)
-                            .format("%Y-%m-%d %H:%M:%S.%3f")
-                            .to_string(),
-                        stack_trace: t.clone(),
-                        os_thread_id: format!("0x{:x}", thread_id),
-                    })
-                }),
-            _ => None,
-        }
+        let thread_id = self.chart_state.highlighted_thread_id?;
+        let thread = self
+            .profile
+            .filtered_threads()
+            .iter()
+            .find(|t| t.id == thread_id)?;
+        let (timestamp_nanos, stack_trace_key, mut info) =
+            match self.chart_state.highlighted_sample? {
+                HighlightedSample::Execution(idx) => {
+                    let s = self.profile.per_thread_samples.get(&thread_id)?.get(idx)?;
+                    let info = ExecutionSampleInfo {
+                        event_type: Some(s.event_type),
+                        state: Some(s.state),
+                        ..Default::default()
+                    };
+                    (s.timestamp_nanos, s.stack_trace_key, info)
+                }
+                HighlightedSample::Contention(idx) => {
+                    let s = self
+                        .profile
+                        .per_thread_contention_samples
+                        .get(&thread_id)?
+                        .get(idx)?;
+                    let contention = ContentionInfo {
+                        kind: s.kind,
+                        duration: format!("{:.3} ms", s.duration_nanos as f64 / 1_000_000.0),
+                        monitor_class: s
+                            .monitor_class_key
+                            .and_then(|k| self.profile.class_name_pool.get(&k))
+                            .cloned(),
+                        // the owner may not have any row, then only its id is known
+                        previous_owner: s.previous_owner.map(|id| {
+                            self.profile
+                                .threads
+                                .iter()
+                                .find(|t| t.java_thread_id == id)
+                                .map(|t| format!("{} (tid: {})", t.name, id))
+                                .unwrap_or_else(|| format!("tid: {}", id))
+                        }),
+                    };
+                    let info = ExecutionSampleInfo {
+                        contention: Some(contention),
+                        ..Default::default()
+                    };
+                    (s.timestamp_nanos, s.stack_trace_key, info)
+                }
+            };
+        let stack_trace = self.profile.stack_trace_pool.get(&stack_trace_key)?;
+        info.timestamp = self.format_timestamp(timestamp_nanos, 9);
+        info.stack_trace = stack_trace.clone();
+        info.frame_signatures = stack_trace.frames.iter().map(|f| f.signature()).collect();
+        info.os_thread_id = format!("0x{:x}", thread.os_thread_id);
+        Some(info)
     }
 
     fn on_mouse_move(&mut self, x: Option<f32>, y: f32) -> Result<()> {
-        let thread_idx = (y / self.row_height()) as usize;
-        let thread_id = self
-            .profile
-            .filtered_threads()
-            .get(thread_idx)
-            .map(|t| t.os_thread_id);
+        let row_idx = (y / self.row_height()) as usize;
+        let thread = match self.rows.get(row_idx) {
+            Some(Row::Thread(idx)) => self.profile.filtered_threads().get(*idx),
+            _ => None,
+        };
+        let thread_id = thread.map(|t| t.id);
 
+        // (sample, x, width, y, timestamp)
         let mut highlighted_sample = None;
         if let Some(thread_id) = thread_id {
             if let (Some(samples), Some(x)) = (self.profile.per_thread_samples.get(&thread_id), x) {
@@ -388,17 +616,53 @@ impl Renderer {
                             * self.elapsed_ratio(next_sample.timestamp_nanos);
                     }
                     if sample_x <= x && x <= right_bound {
-                        highlighted_sample =
-                            Some((i, sample_x, thread_idx as f32 * self.row_height()));
+                        highlighted_sample = Some((
+                            HighlightedSample::Execution(i),
+                            sample_x,
+                            self.chart_config
+                                .sample_view_config
+                                .sample_render_size
+                                .width,
+                            row_idx as f32 * self.row_height(),
+                            sample.timestamp_nanos,
+                        ));
+                        break;
+                    }
+                }
+            }
+        }
+        // contentions are drawn behind execution samples, so they are looked up only if
+        // no execution sample is under the mouse
+        if let (None, Some(thread), Some(x)) = (highlighted_sample, thread, x) {
+            if let Some(samples) = self.profile.per_thread_contention_samples.get(&thread.id) {
+                for (i, sample) in samples.iter().enumerate() {
+                    if !self.profile.is_valid_sample(thread, sample) {
+                        continue;
+                    }
+                    let sample_x =
+                        self.sample_view_width() * self.elapsed_ratio(sample.timestamp_nanos);
+                    let width = (self.sample_view_width()
+                        * self.elapsed_ratio(sample.timestamp_nanos + sample.duration_nanos)
+                        - sample_x)
+                        .max(1.0);
+                    if sample_x <= x && x <= sample_x + width {
+                        highlighted_sample = Some((
+                            HighlightedSample::Contention(i),
+                            sample_x,
+                            width,
+                            row_idx as f32 * self.row_height(),
+                            sample.timestamp_nanos,
+                        ));
                         break;
                     }
                 }
             }
         }
 
-        let sample_idx = highlighted_sample.map(|s| s.0);
-        if thread_id != self.chart_state.highlighted_thread_id
-            || sample_idx != self.chart_state.highlighted_sample_idx
+        let sample = highlighted_sample.map(|s| s.0);
+        if Some(row_idx) != self.chart_state.highlighted_row_idx
+            || thread_id != self.chart_state.highlighted_thread_id
+            || sample != self.chart_state.highlighted_sample
         {
             self.header_overlay.clear();
             self.chart_overlay.clear();
@@ -406,8 +670,8 @@ impl Renderer {
             self.chart_overlay.ctx.set_fill_style(&self.row_highlight);
             self.header_overlay.ctx.set_fill_style(&self.row_highlight);
 
-            let y = (thread_idx as f32 * self.row_height()) as f64
-                - self.chart_pane.scroll_top() as f64;
+            let y =
+                (row_idx as f32 * self.row_height()) as f64 - self.chart_pane.scroll_top() as f64;
             let h = self.row_height() as f64;
 
             self.chart_overlay
@@ -417,17 +681,14 @@ impl Renderer {
                 .ctx
                 .fill_rect(0.0, y, self.header_overlay.raw.width() as f64, h);
 
-            if let Some((idx, x, y)) = highlighted_sample {
+            if let Some((_, x, width, y, timestamp_nanos)) = highlighted_sample {
                 self.chart_overlay
                     .ctx
                     .set_fill_style(&self.sample_highlight);
                 self.chart_overlay.ctx.fill_rect(
                     x as f64 - self.chart_pane.scroll_left() as f64,
                     y as f64 - self.chart_pane.scroll_top() as f64,
-                    self.chart_config
-                        .sample_view_config
-                        .sample_render_size
-                        .width as f64,
+                    width as f64,
                     h,
                 );
 
@@ -442,30 +703,164 @@ impl Renderer {
                     .line_to(overlay_x, self.chart_overlay.raw.height() as f64);
                 self.chart_overlay.ctx.stroke();
 
-                if let Some(thread_id) = thread_id {
-                    if let Some(samples) = self.profile.per_thread_samples.get(&thread_id) {
-                        let timestamp_nanos = samples[idx].timestamp_nanos;
-                        let timestamp = NaiveDateTime::from_timestamp(
-                            timestamp_nanos / 1_000_000_000,
-                            (timestamp_nanos % 1_000_000_000) as u32,
-                        );
-                        let t = Local
-                            .from_utc_datetime(&timestamp)
-                            .format("%Y-%m-%d %H:%M:%S.%3f")
-                            .to_string();
-                        self.time_label.set_text_content(Some(&t));
-                        self.time_label
-                            .style()
-                            .set_property("left", format!("{}px", overlay_x).as_str())?;
-                        self.time_label
-                            .style()
-                            .set_property("display", "inline-block")?;
+                let t = self.format_timestamp(timestamp_nanos, 9);
+                self.time_label.set_text_content(Some(&t));
+                self.time_label
+                    .style()
+                    .set_property("left", format!("{}px", overlay_x).as_str())?;
+                self.time_label
+                    .style()
+                    .set_property("display", "inline-block")?;
+            }
+        }
+        self.chart_state.highlighted_row_idx = Some(row_idx);
+        self.chart_state.highlighted_thread_id = thread_id;
+        self.chart_state.highlighted_sample = sample;
+        Ok(())
+    }
+
+    fn render_thread(&self, thread: &Thread, y: f32) {
+        // render contentions first as spans so that execution samples are drawn over them
+        if let Some(samples) = self.profile.per_thread_contention_samples.get(&thread.id) {
+            for sample in samples.iter() {
+                if !self.profile.is_valid_sample(thread, sample) {
+                    continue;
+                }
+                let x = self.sample_view_width() * self.elapsed_ratio(sample.timestamp_nanos);
+                let x_end = self.sample_view_width()
+                    * self.elapsed_ratio(sample.timestamp_nanos + sample.duration_nanos);
+                let color = match sample.kind {
+                    ContentionKind::MonitorEnter => {
+                        self.chart_config
+                            .contention_color_config
+                            .monitor_enter_rgb_hex
+                    }
+                    ContentionKind::ThreadPark => {
+                        self.chart_config
+                            .contention_color_config
+                            .thread_park_rgb_hex
                     }
+                };
+
+                self.chart
+                    .ctx
+                    .set_fill_style(&JsValue::from_str(format!("#{:x}", color).as_str()));
+                self.chart.ctx.fill_rect(
+                    x as f64,
+                    y as f64,
+                    (x_end - x).max(1.0) as f64,
+                    self.chart_config
+                        .sample_view_config
+                        .sample_render_size
+                        .height as f64,
+                );
+            }
+        }
+        if let Some(samples) = self.profile.per_thread_samples.get(&thread.id) {
+            for (_j, sample) in samples.iter().enumerate() {
+                let x = self.sample_view_width() * self.elapsed_ratio(sample.timestamp_nanos);
+                let color = self.sample_color(thread, sample);
+
+                self.chart
+                    .ctx
+                    .set_fill_style(&JsValue::from_str(format!("#{:x}", color).as_str()));
+                self.chart.ctx.fill_rect(
+                    x as f64,
+                    y as f64,
+                    self.chart_config
+                        .sample_view_config
+                        .sample_render_size
+                        .width as f64,
+                    self.chart_config
+                        .sample_view_config
+                        .sample_render_size
+                        .height as f64,
+                );
+            }
+        }
+    }
+
+    /// Render the aggregate of the member threads as stacked bars of state counts per time bucket
+    fn render_group(&self, threads: &[&Thread], y: f32) {
+        let config = &self.chart_config.thread_state_color_config;
+        let size = &self.chart_config.sample_view_config.sample_render_size;
+        for (i, counts) in group::state_counts(&self.profile, threads, self.profile.column_count)
+            .iter()
+            .enumerate()
+        {
+            let total = counts.total();
+            if total == 0 {
+                continue;
+            }
+            let mut offset = 0.0;
+            let state_counts = ThreadState::ALL
+                .iter()
+                .map(|&state| (counts.state(state), config.state_color(state)));
+            for (count, color) in state_counts.chain([
+                (counts.native_method, config.native_method_sample_rgb_hex),
+                (counts.hidden, config.state_hidden_rgb_hex),
+            ]) {
+                if count == 0 {
+                    continue;
                 }
+                let height = size.height * count as f32 / total as f32;
+                self.chart
+                    .ctx
+                    .set_fill_style(&JsValue::from_str(format!("#{:x}", color).as_str()));
+                self.chart.ctx.fill_rect(
+                    (size.width * i as f32) as f64,
+                    (y + offset) as f64,
+                    size.width as f64,
+                    height as f64,
+                );
+                offset += height;
             }
         }
-        self.chart_state.highlighted_thread_id = thread_id;
-        self.chart_state.highlighted_sample_idx = sample_idx;
+    }
+
+    fn sample_color(&self, thread: &Thread, sample: &ExecutionSample) -> u32 {
+        if !self.profile.is_valid_sample(thread, sample) {
+            self.chart_config
+                .thread_state_color_config
+                .state_hidden_rgb_hex
+        } else if sample.event_type == SampleEventType::NativeMethodSample {
+            self.chart_config
+                .thread_state_color_config
+                .native_method_sample_rgb_hex
+        } else {
+            self.chart_config
+                .thread_state_color_config
+                .state_color(sample.state)
+        }
+    }
+
+    /// Whether the row is a member of the expanded group above it
+    fn is_group_member(&self, row_idx: usize) -> bool {
+        self.rows[..row_idx]
+            .iter()
+            .rev()
+            .find(|r| matches!(r, Row::Group { .. }))
+            .map(|r| match (r, &self.rows[row_idx]) {
+                (
+                    Row::Group {
+                        thread_indices,
+                        expanded: true,
+                        ..
+                    },
+                    Row::Thread(idx),
+                ) => thread_indices.contains(idx),
+                _ => false,
+            })
+            .unwrap_or(false)
+    }
+
+    fn refresh_rows(&mut self) -> Result<()> {
+        self.rows = group::rows(
+            self.profile.filtered_threads(),
+            &self.thread_group_config,
+            &self.expanded_groups,
+        )
+        .map_err(Self::map_js_value)?;
         Ok(())
     }
 
@@ -478,8 +873,14 @@ impl Renderer {
     }
 
     fn elapsed_ratio(&self, timestamp_nanos: i64) -> f32 {
-        ((timestamp_nanos - (self.profile.interval.start_millis * 1000000)) as f64
-            / (self.profile.interval.duration_millis() * 1000000) as f64) as f32
+        self.profile.interval.elapsed_ratio(timestamp_nanos) as f32
+    }
+
+    /// The timestamp at the x coordinate on the chart
+    fn timestamp_at(&self, x: f32) -> i64 {
+        self.profile
+            .interval
+            .timestamp_at((x / self.sample_view_width()) as f64)
     }
 
     fn row_height(&self) -> f32 {
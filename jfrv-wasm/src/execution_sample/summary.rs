@@ -0,0 +1,273 @@
+//! Summary of the recording, which is built while loading so that users can check
+//! what's inside the file before relying on it.
+//! Should not contain any wasm dependencies.
+
+use crate::profile::{ConstantPoolKey, ExecutionSample, StackTrace};
+use jfrs::reader::event::{Accessor, Event};
+use jfrs::reader::Chunk;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+#[cfg(target_arch = "wasm32")]
+use tsify::Tsify;
+
+const EVENT_JVM_INFORMATION: &str = "jdk.JVMInformation";
+const EVENT_OS_INFORMATION: &str = "jdk.OSInformation";
+const EVENT_ACTIVE_SETTING: &str = "jdk.ActiveSetting";
+/// Fields common to all events, which aren't informative in the summary
+const EVENT_HEADER_FIELDS: [&str; 4] = ["startTime", "duration", "eventThread", "stackTrace"];
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[cfg_attr(target_arch = "wasm32", derive(Tsify))]
+#[cfg_attr(target_arch = "wasm32", tsify(into_wasm_abi, from_wasm_abi))]
+#[serde(rename_all = "camelCase")]
+pub struct RecordingSummary {
+    pub chunks: Vec<ChunkSummary>,
+    /// Number of events per event type, sorted by the event type name
+    pub event_counts: Vec<EventCount>,
+    /// Fields of jdk.JVMInformation
+    pub jvm_information: Vec<Field>,
+    /// Fields of jdk.OSInformation
+    pub os_information: Vec<Field>,
+    /// The latest value of each jdk.ActiveSetting, sorted by the event type and the setting name
+    pub active_settings: Vec<ActiveSetting>,
+    /// Number of distinct stack traces whose root side frames were dropped
+    pub truncated_stack_traces: u64,
+    /// Number of execution samples having truncated stack traces
+    pub truncated_execution_samples: u64,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[cfg_attr(target_arch = "wasm32", derive(Tsify))]
+#[cfg_attr(target_arch = "wasm32", tsify(into_wasm_abi, from_wasm_abi))]
+#[serde(rename_all = "camelCase")]
+pub struct ChunkSummary {
+    pub start_time_nanos: i64,
+    pub duration_nanos: i64,
+    pub ticks_per_second: i64,
+    pub size: i64,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[cfg_attr(target_arch = "wasm32", derive(Tsify))]
+#[cfg_attr(target_arch = "wasm32", tsify(into_wasm_abi, from_wasm_abi))]
+#[serde(rename_all = "camelCase")]
+pub struct EventCount {
+    pub event_type: String,
+    pub count: u64,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[cfg_attr(target_arch = "wasm32", derive(Tsify))]
+#[cfg_attr(target_arch = "wasm32", tsify(into_wasm_abi, from_wasm_abi))]
+#[serde(rename_all = "camelCase")]
+pub struct Field {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[cfg_attr(target_arch = "wasm32", derive(Tsify))]
+#[cfg_attr(target_arch = "wasm32", tsify(into_wasm_abi, from_wasm_abi))]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveSetting {
+    pub event_type: String,
+    pub name: String,
+    pub value: String,
+}
+
+/// Accumulates the summary while loading chunks
+#[derive(Default)]
+pub(crate) struct SummaryBuilder {
+    chunks: Vec<ChunkSummary>,
+    event_counts: BTreeMap<String, u64>,
+    jvm_information: Vec<Field>,
+    os_information: Vec<Field>,
+    active_settings: BTreeMap<(String, String), String>,
+}
+
+impl SummaryBuilder {
+    pub(crate) fn add_chunk(&mut self, chunk: &Chunk) {
+        self.chunks.push(ChunkSummary {
+            start_time_nanos: chunk.header.start_time_nanos,
+            duration_nanos: chunk.header.duration_nanos,
+            ticks_per_second: chunk.header.ticks_per_second,
+            size: chunk.header.chunk_size,
+        });
+    }
+
+    pub(crate) fn add_event(&mut self, chunk: &Chunk, event: &Event<'_>) {
+        let name = event.class.name();
+        match self.event_counts.get_mut(name) {
+            Some(count) => *count += 1,
+            None => {
+                self.event_counts.insert(name.to_string(), 1);
+            }
+        }
+
+        match name {
+            // these events are emitted on every chunk, so the latest one wins
+            EVENT_JVM_INFORMATION => self.jvm_information = Self::fields(event),
+            EVENT_OS_INFORMATION => self.os_information = Self::fields(event),
+            EVENT_ACTIVE_SETTING => {
+                let value = event.value();
+                let event_type = value
+                    .get_field("id")
+                    .and_then(|id| i64::try_from(id.value).ok())
+                    .and_then(|id| chunk.metadata.type_pool.get(id))
+                    .map(|t| t.name().to_string())
+                    .unwrap_or_default();
+                if let (Some(name), Some(value)) = (
+                    value.get_field("name").and_then(Self::to_string),
+                    value.get_field("value").and_then(Self::to_string),
+                ) {
+                    self.active_settings.insert((event_type, name), value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn build(
+        self,
+        stack_trace_pool: &FxHashMap<ConstantPoolKey, StackTrace>,
+        per_thread_samples: &FxHashMap<i64, Vec<ExecutionSample>>,
+    ) -> RecordingSummary {
+        let is_truncated = |key: &ConstantPoolKey| {
+            stack_trace_pool
+                .get(key)
+                .map(|t| t.truncated)
+                .unwrap_or(false)
+        };
+        RecordingSummary {
+            chunks: self.chunks,
+            event_counts: self
+                .event_counts
+                .into_iter()
+                .map(|(event_type, count)| EventCount { event_type, count })
+                .collect(),
+            jvm_information: self.jvm_information,
+            os_information: self.os_information,
+            active_settings: self
+                .active_settings
+                .into_iter()
+                .map(|((event_type, name), value)| ActiveSetting {
+                    event_type,
+                    name,
+                    value,
+                })
+                .collect(),
+            truncated_stack_traces: stack_trace_pool.values().filter(|t| t.truncated).count()
+                as u64,
+            truncated_execution_samples: per_thread_samples
+                .values()
+                .flatten()
+                .filter(|s| is_truncated(&s.stack_trace_key))
+                .count() as u64,
+        }
+    }
+
+    /// Primitive fields of the event except the common header fields
+    fn fields(event: &Event<'_>) -> Vec<Field> {
+        event
+            .class
+            .fields
+            .iter()
+            .filter(|f| !EVENT_HEADER_FIELDS.contains(&f.name()))
+            .filter_map(|f| {
+                event
+                    .value()
+                    .get_field(f.name())
+                    .and_then(Self::to_string)
+                    .map(|value| Field {
+                        name: f.name().to_string(),
+                        value,
+                    })
+            })
+            .collect()
+    }
+
+    fn to_string(accessor: Accessor<'_>) -> Option<String> {
+        let value = accessor.value;
+        <&str>::try_from(value)
+            .map(|s| s.to_string())
+            .or_else(|_| i64::try_from(value).map(|v| v.to_string()))
+            .or_else(|_| i32::try_from(value).map(|v| v.to_string()))
+            .or_else(|_| i16::try_from(value).map(|v| v.to_string()))
+            .or_else(|_| i8::try_from(value).map(|v| v.to_string()))
+            .or_else(|_| bool::try_from(value).map(|v| v.to_string()))
+            .or_else(|_| f64::try_from(value).map(|v| v.to_string()))
+            .or_else(|_| f32::try_from(value).map(|v| v.to_string()))
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::execution_sample::Profile;
+    use crate::jfr;
+    use crate::test_util::load;
+
+    #[test]
+    fn test_summary() {
+        let profile = load("profiler-multichunk.jfr");
+        let summary = &profile.summary;
+
+        assert_eq!(summary.chunks.len(), 3);
+        assert!(summary
+            .chunks
+            .windows(2)
+            .all(|w| w[0].start_time_nanos <= w[1].start_time_nanos));
+        assert!(summary.chunks.iter().all(|c| c.ticks_per_second > 0));
+
+        let execution_samples = summary
+            .event_counts
+            .iter()
+            .find(|c| c.event_type == "jdk.ExecutionSample")
+            .unwrap();
+        assert_eq!(execution_samples.count, 2960 + 2960 + 2968);
+        assert!(summary
+            .event_counts
+            .windows(2)
+            .all(|w| w[0].event_type < w[1].event_type));
+    }
+
+    #[test]
+    fn test_jvm_information() {
+        let profile = load("profiler-wall.jfr");
+        let summary = &profile.summary;
+
+        assert!(summary
+            .jvm_information
+            .iter()
+            .any(|f| f.name == "jvmVersion" && !f.value.is_empty()));
+        assert!(summary
+            .os_information
+            .iter()
+            .any(|f| f.name == "osVersion" && !f.value.is_empty()));
+        assert!(summary
+            .active_settings
+            .iter()
+            .any(|s| s.event_type == "jdk.ExecutionSample"));
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut profile = load("profiler-wall.jfr");
+        assert_eq!(profile.summary.truncated_stack_traces, 0);
+
+        let key = *profile.stack_trace_pool.keys().next().unwrap();
+        profile.stack_trace_pool.get_mut(&key).unwrap().truncated = true;
+        let expected = profile
+            .per_thread_samples
+            .values()
+            .flatten()
+            .filter(|s| s.stack_trace_key == key)
+            .count() as u64;
+
+        let mut reloaded = Profile::default();
+        reloaded.load(jfr::write(&profile).unwrap()).unwrap();
+        assert_eq!(reloaded.summary.truncated_stack_traces, 1);
+        assert_eq!(reloaded.summary.truncated_execution_samples, expected);
+    }
+}
@@ -0,0 +1,214 @@
+//! Folded (a.k.a. collapsed) stacks format, which consists of `frame;frame;frame count` lines.
+//! The format is produced by async-profiler's `collapsed` output and Brendan Gregg's
+//! stackcollapse scripts.
+
+use crate::flame_graph::{FlameGraph, Frame};
+use crate::profile::{FrameType, StackFrame, StackTrace};
+use anyhow::{anyhow, Result};
+
+/// Frame type annotations appended by async-profiler
+const SUFFIXES: [(&str, FrameType); 5] = [
+    ("_[j]", FrameType::JitCompiled),
+    ("_[i]", FrameType::Inlined),
+    ("_[k]", FrameType::Kernel),
+    ("_[0]", FrameType::Interpreted),
+    ("_[1]", FrameType::C1Compiled),
+];
+
+/// Parse folded stacks into a flame graph.
+pub fn parse(input: &str) -> Result<FlameGraph> {
+    let mut flame = FlameGraph::new();
+
+    for (i, line) in input.lines().enumerate() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            continue;
+        }
+        let (stack, count) = line
+            .rsplit_once(' ')
+            .ok_or_else(|| anyhow!("line {}: missing sample count", i + 1))?;
+        let count: u64 = count
+            .parse()
+            .map_err(|e| anyhow!("line {}: invalid sample count: {}", i + 1, e))?;
+
+        // folded stacks are root-first while StackTrace is leaf-first
+        let frames = stack
+            .split(';')
+            .filter(|f| !f.is_empty())
+            .rev()
+            .map(parse_frame)
+            .collect();
+        flame.add_sample(
+            &StackTrace {
+                frames,
+                truncated: false,
+            },
+            count,
+        );
+    }
+
+    Ok(flame)
+}
+
+/// Format the flame graph as folded stacks.
+/// Java and kernel frames are annotated with async-profiler's frame type suffix
+/// so the output can be parsed back without losing coloring.
+pub fn format(flame_graph: &FlameGraph) -> String {
+    let mut out = String::new();
+    let mut path = vec![];
+    for (id, child) in flame_graph.root.children.iter() {
+        format_frame(id.name.as_str(), child, &mut path, &mut out);
+    }
+    out
+}
+
+fn format_frame(name: &str, frame: &Frame, path: &mut Vec<String>, out: &mut String) {
+    let suffix = SUFFIXES
+        .iter()
+        .find(|(_, frame_type)| *frame_type == frame.calculated_type())
+        .map(|(suffix, _)| *suffix)
+        .unwrap_or("");
+    path.push(format!("{}{}", name, suffix));
+
+    if frame.self_count > 0 {
+        out.push_str(path.join(";").as_str());
+        out.push_str(format!(" {}\n", frame.self_count).as_str());
+    }
+    for (id, child) in frame.children.iter() {
+        format_frame(id.name.as_str(), child, path, out);
+    }
+
+    path.pop();
+}
+
+fn parse_frame(frame: &str) -> StackFrame {
+    let (name, frame_type) = SUFFIXES
+        .iter()
+        .find_map(|(suffix, frame_type)| frame.strip_suffix(suffix).map(|name| (name, *frame_type)))
+        .unwrap_or_else(|| (frame, infer_frame_type(frame)));
+
+    stack_frame(name, frame_type, 0)
+}
+
+/// Build a stack frame from the display name, splitting Java frames into type and method
+pub(crate) fn stack_frame(name: &str, frame_type: FrameType, line_number: i32) -> StackFrame {
+    match frame_type {
+        FrameType::Interpreted
+        | FrameType::JitCompiled
+        | FrameType::Inlined
+        | FrameType::C1Compiled => match name.rsplit_once('.') {
+            Some((type_name, method_name)) => StackFrame::new(
+                type_name.to_string(),
+                method_name.to_string(),
+                frame_type,
+                line_number,
+            ),
+            None => StackFrame::new("".to_string(), name.to_string(), frame_type, line_number),
+        },
+        _ => StackFrame::new("".to_string(), name.to_string(), frame_type, line_number),
+    }
+}
+
+/// Infer the frame type of the frame without annotation in the same way as async-profiler
+pub(crate) fn infer_frame_type(name: &str) -> FrameType {
+    if name.contains("::") || name.starts_with("-[") || name.starts_with("+[") {
+        FrameType::Cpp
+    } else if (name.find('/').map(|i| i > 0).unwrap_or(false) && !name.starts_with('['))
+        || (name.find('.').map(|i| i > 0).unwrap_or(false)
+            && name.starts_with(|c: char| c.is_ascii_uppercase()))
+    {
+        FrameType::JitCompiled
+    } else {
+        FrameType::Native
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::execution_sample::Filter;
+    use crate::flame_graph::folded::{format, parse};
+    use crate::flame_graph::FlameGraph;
+    use crate::profile::FrameType;
+    use crate::test_util::load;
+
+    #[test]
+    fn test_parse() {
+        let input = "\
+java/lang/Thread.run_[j];Example.work_[i];write_[k] 3
+java/lang/Thread.run_[j];Example.work_[i] 2
+thread_native_entry;Thread::call_run;java/lang/Thread.run 5
+
+";
+        let flame = parse(input).unwrap();
+        assert_eq!(flame.depth, 3);
+        assert_eq!(flame.root.total_count, 10);
+
+        let children: Vec<_> = flame.root.children.iter().collect();
+        assert_eq!(children.len(), 2);
+        let (id, run) = children[0];
+        assert_eq!(id.name, "java/lang/Thread.run");
+        assert_eq!(run.total_count, 5);
+        assert_eq!(run.calculated_type(), FrameType::JitCompiled);
+
+        let (id, work) = run.children.iter().next().unwrap();
+        assert_eq!(id.name, "Example.work");
+        assert_eq!(work.calculated_type(), FrameType::Inlined);
+        assert_eq!(work.self_count, 2);
+        let (id, write) = work.children.iter().next().unwrap();
+        assert_eq!(id.name, "write");
+        assert_eq!(write.calculated_type(), FrameType::Kernel);
+
+        let (id, entry) = children[1];
+        assert_eq!(id.name, "thread_native_entry");
+        assert_eq!(entry.calculated_type(), FrameType::Native);
+        let (id, call_run) = entry.children.iter().next().unwrap();
+        assert_eq!(id.name, "Thread::call_run");
+        assert_eq!(call_run.calculated_type(), FrameType::Cpp);
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let err = parse("a;b 1\na;b\n").err().unwrap();
+        assert!(err.to_string().starts_with("line 2:"));
+        assert!(parse("a;b x").is_err());
+    }
+
+    #[test]
+    fn test_format() {
+        let input = "\
+java/lang/Thread.run_[j];Example.work_[i];write_[k] 3
+java/lang/Thread.run_[j];Example.work_[i] 2
+thread_native_entry;Thread::call_run;java/lang/Thread.run_[0] 5
+";
+        let mut lines: Vec<_> = input.lines().collect();
+        lines.sort();
+        let output = format(&parse(input).unwrap());
+        let mut output_lines: Vec<_> = output.lines().collect();
+        output_lines.sort();
+        assert_eq!(output_lines, lines);
+    }
+
+    #[test]
+    fn test_format_filtered() {
+        let mut profile = load("profiler-wall.jfr");
+        profile
+            .apply_filter(Filter {
+                thread_name_regex: Some("GC Thread".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let flame = FlameGraph::from_execution_sample(&profile, &Default::default());
+        let output = format(&flame);
+        let total: u64 = output
+            .lines()
+            .map(|l| l.rsplit_once(' ').unwrap().1.parse::<u64>().unwrap())
+            .sum();
+        assert!(0 < total && total < 8836);
+        assert_eq!(total, flame.root.total_count);
+
+        let reparsed = parse(output.as_str()).unwrap();
+        assert_eq!(reparsed.root.total_count, flame.root.total_count);
+        assert_eq!(reparsed.depth, flame.depth);
+    }
+}
@@ -1,14 +1,157 @@
 use crate::execution_sample::Profile;
+use crate::flame_graph::normalize::{NormalizationConfig, Normalizer};
 use crate::profile::{FrameType, StackFrame, StackTrace};
+use anyhow::Result;
 use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::collections::BTreeMap;
+#[cfg(target_arch = "wasm32")]
+use tsify::Tsify;
 
+pub mod folded;
+pub mod normalize;
 #[cfg(target_arch = "wasm32")]
 pub mod render;
 
+/// How to weight each allocation sample in the flame graph
+#[derive(Copy, Clone, Deserialize, Serialize)]
+#[cfg_attr(target_arch = "wasm32", derive(Tsify))]
+#[cfg_attr(target_arch = "wasm32", tsify(into_wasm_abi, from_wasm_abi))]
+pub enum AllocationWeight {
+    Bytes,
+    Count,
+}
+
+/// Name of the synthetic root frame of truncated stack traces
+pub const TRUNCATED_FRAME_NAME: &str = "[truncated]";
+
+/// Node name of the classes in the unnamed package at the package granularity
+const DEFAULT_PACKAGE: &str = "(default package)";
+
+/// How frames are merged into the flame graph nodes
+#[derive(Copy, Clone, Default, Deserialize, Serialize)]
+#[cfg_attr(target_arch = "wasm32", derive(Tsify))]
+#[cfg_attr(target_arch = "wasm32", tsify(into_wasm_abi, from_wasm_abi))]
+#[serde(rename_all = "camelCase")]
+pub struct AggregationConfig {
+    pub normalization: NormalizationConfig,
+    /// Name frames with parameter types so that overloaded methods are distinguished
+    pub signature: bool,
+    pub granularity: Granularity,
+}
+
+/// The unit of Java frames to be merged into a flame graph node.
+/// Native and kernel frames are always merged by the name.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+#[cfg_attr(target_arch = "wasm32", derive(Tsify))]
+#[cfg_attr(target_arch = "wasm32", tsify(into_wasm_abi, from_wasm_abi))]
+pub enum Granularity {
+    #[default]
+    Line,
+    Method,
+    Class,
+    Package,
+}
+
+/// Compiled AggregationConfig
+#[derive(Default)]
+pub struct Aggregator {
+    normalizer: Normalizer,
+    signature: bool,
+    granularity: Granularity,
+}
+
+impl Aggregator {
+    pub fn new(config: &AggregationConfig) -> Self {
+        Self {
+            normalizer: Normalizer::new(&config.normalization),
+            signature: config.signature,
+            granularity: config.granularity,
+        }
+    }
+
+    /// The id of the flame graph node which the frame belongs to
+    pub fn frame_id(&self, frame: &StackFrame) -> FrameId {
+        FrameId {
+            name: self.frame_name(frame),
+            category: Frame::categorize(frame.frame_type).1,
+        }
+    }
+
+    /// Frames of the stack trace from the root along with the ids of the nodes they belong to.
+    /// Consecutive frames rolled up into the same node are merged into the outermost one
+    pub fn aggregate<'a>(&self, stack_trace: &'a StackTrace) -> Vec<(&'a StackFrame, FrameId)> {
+        let mut frames: Vec<(&StackFrame, FrameId)> = Vec::with_capacity(stack_trace.frames.len());
+        for f in stack_trace.frames.iter().rev() {
+            let id = self.frame_id(f);
+            if self.merges_consecutive_frames() && frames.last().map(|(_, last)| last) == Some(&id)
+            {
+                continue;
+            }
+            frames.push((f, id));
+        }
+        frames
+    }
+
+    /// Whether consecutive frames having the same id should be merged into a node,
+    /// which is the case when a call within a class or a package is rolled up
+    fn merges_consecutive_frames(&self) -> bool {
+        matches!(self.granularity, Granularity::Class | Granularity::Package)
+    }
+
+    fn frame_name(&self, frame: &StackFrame) -> String {
+        let normalized = match self.normalizer.normalize(frame.type_name.as_str()) {
+            Cow::Borrowed(_) => None,
+            Cow::Owned(type_name) => Some(
+                StackFrame::new(
+                    type_name,
+                    frame.method_name.clone(),
+                    frame.frame_type,
+                    frame.line_number,
+                )
+                .with_method_signature(frame.descriptor.clone(), frame.modifiers),
+            ),
+        };
+        let frame = normalized.as_ref().unwrap_or(frame);
+        if frame.type_name.is_empty()
+            || Frame::categorize(frame.frame_type).1 != FrameCategory::Java
+        {
+            return frame.name().to_string();
+        }
+
+        match self.granularity {
+            Granularity::Line if self.signature => frame.signature_name(),
+            Granularity::Line => frame.name().to_string(),
+            Granularity::Method => {
+                let method = StackFrame::new(
+                    frame.type_name.clone(),
+                    frame.method_name.clone(),
+                    frame.frame_type,
+                    0,
+                )
+                .with_method_signature(frame.descriptor.clone(), frame.modifiers);
+                if self.signature {
+                    method.signature_name()
+                } else {
+                    method.name().to_string()
+                }
+            }
+            Granularity::Class => frame.type_name.clone(),
+            Granularity::Package => {
+                match normalize::strip_hidden_class_suffix(&frame.type_name).rsplit_once('/') {
+                    Some((package, _)) => package.to_string(),
+                    None => DEFAULT_PACKAGE.to_string(),
+                }
+            }
+        }
+    }
+}
+
 pub struct FlameGraph {
     pub depth: usize,
     pub root: Frame,
+    aggregator: Aggregator,
 }
 
 impl Default for FlameGraph {
@@ -19,26 +162,31 @@ impl Default for FlameGraph {
 
 impl FlameGraph {
     pub fn new() -> Self {
+        Self::with_config(&AggregationConfig::default())
+    }
+
+    pub fn with_config(config: &AggregationConfig) -> Self {
         Self {
             depth: 0,
             root: Frame {
                 base_type: FrameType::Native,
                 ..Default::default()
             },
+            aggregator: Aggregator::new(config),
         }
     }
 
-    pub fn from_execution_sample(profile: &Profile) -> Self {
-        let mut flame = Self::new();
+    pub fn from_execution_sample(profile: &Profile, config: &AggregationConfig) -> Self {
+        let mut flame = Self::with_config(config);
 
         let mut pre_aggregation = FxHashMap::default();
         for thread in profile.filtered_threads() {
-            if let Some(samples) = profile.per_thread_samples.get(&thread.os_thread_id) {
-                for sample in samples {
-                    if profile.is_valid_sample(sample) {
+            if let Some(samples) = profile.per_thread_samples.get(&thread.id) {
+                for sample in profile.samples_in_time_range(samples) {
+                    if profile.is_valid_sample(thread, sample) {
                         let count = pre_aggregation
                             .get(&sample.stack_trace_key)
-                            .unwrap_or(&0usize)
+                            .unwrap_or(&0u64)
                             + 1;
                         pre_aggregation.insert(sample.stack_trace_key, count);
                     }
@@ -55,23 +203,152 @@ impl FlameGraph {
         flame
     }
 
-    pub fn add_sample(&mut self, stack_trace: &StackTrace, count: usize) {
+    /// Build a flame graph from folded stacks, i.e. `frame;frame;frame count` lines.
+    pub fn from_folded(input: &str) -> Result<Self> {
+        folded::parse(input)
+    }
+
+    /// Format the flame graph as folded stacks, i.e. `frame;frame;frame count` lines.
+    pub fn to_folded(&self) -> String {
+        folded::format(self)
+    }
+
+    /// Build a flame graph of allocations.
+    /// The allocated object's class is added as the top frame like async-profiler does
+    /// when it's known.
+    pub fn from_allocation_sample(
+        profile: &Profile,
+        weight: AllocationWeight,
+        config: &AggregationConfig,
+    ) -> Self {
+        let mut flame = Self::with_config(config);
+
+        let mut pre_aggregation = FxHashMap::default();
+        for thread in profile.filtered_threads() {
+            if let Some(samples) = profile.per_thread_allocation_samples.get(&thread.id) {
+                for sample in profile.samples_in_time_range(samples) {
+                    if profile.is_valid_sample(thread, sample) {
+                        let value = match weight {
+                            AllocationWeight::Bytes => sample.allocation_size.max(0) as u64,
+                            AllocationWeight::Count => 1,
+                        };
+                        *pre_aggregation
+                            .entry((
+                                sample.stack_trace_key,
+                                sample.object_class_key,
+                                sample.outside_tlab,
+                            ))
+                            .or_insert(0u64) += value;
+                    }
+                }
+            }
+        }
+
+        for ((stack_trace_key, object_class_key, outside_tlab), &v) in pre_aggregation.iter() {
+            if let Some(trace) = profile.stack_trace_pool.get(stack_trace_key) {
+                match object_class_key.and_then(|k| profile.class_name_pool.get(&k)) {
+                    Some(class_name) => {
+                        let frame_type = if *outside_tlab {
+                            FrameType::Kernel
+                        } else {
+                            FrameType::Inlined
+                        };
+                        flame.add_sample_with_class_frame(trace, class_name, frame_type, v)
+                    }
+                    None => flame.add_sample(trace, v),
+                }
+            }
+        }
+
+        flame
+    }
+
+    /// Build a flame graph of lock contentions weighted by blocked time in nanoseconds.
+    /// The monitor class is added as the top frame when it's known.
+    pub fn from_contention_sample(profile: &Profile, config: &AggregationConfig) -> Self {
+        let mut flame = Self::with_config(config);
+
+        let mut pre_aggregation = FxHashMap::default();
+        for thread in profile.filtered_threads() {
+            if let Some(samples) = profile.per_thread_contention_samples.get(&thread.id) {
+                for sample in profile.samples_in_time_range(samples) {
+                    if profile.is_valid_sample(thread, sample) {
+                        *pre_aggregation
+                            .entry((sample.stack_trace_key, sample.monitor_class_key))
+                            .or_insert(0u64) += sample.duration_nanos.max(0) as u64;
+                    }
+                }
+            }
+        }
+
+        for ((stack_trace_key, monitor_class_key), &v) in pre_aggregation.iter() {
+            if let Some(trace) = profile.stack_trace_pool.get(stack_trace_key) {
+                match monitor_class_key.and_then(|k| profile.class_name_pool.get(&k)) {
+                    Some(class_name) => {
+                        flame.add_sample_with_class_frame(trace, class_name, FrameType::Kernel, v)
+                    }
+                    None => flame.add_sample(trace, v),
+                }
+            }
+        }
+
+        flame
+    }
+
+    fn add_sample_with_class_frame(
+        &mut self,
+        stack_trace: &StackTrace,
+        class_name: &str,
+        frame_type: FrameType,
+        count: u64,
+    ) {
+        let mut frames = Vec::with_capacity(stack_trace.frames.len() + 1);
+        frames.push(StackFrame::new(
+            "".to_string(),
+            class_name.to_string(),
+            frame_type,
+            0,
+        ));
+        frames.extend(stack_trace.frames.iter().cloned());
+        self.add_sample(
+            &StackTrace {
+                frames,
+                truncated: stack_trace.truncated,
+            },
+            count,
+        );
+    }
+
+    pub fn add_sample(&mut self, stack_trace: &StackTrace, count: u64) {
         let mut frame = &mut self.root;
-        for f in stack_trace.frames.iter().rev() {
-            frame = frame.add_child(f, count);
+        let mut depth = 0;
+        if stack_trace.truncated {
+            // group truncated stacks so that their partial roots don't look like real roots
+            let truncated = StackFrame::new(
+                "".to_string(),
+                TRUNCATED_FRAME_NAME.to_string(),
+                FrameType::Unknown,
+                0,
+            );
+            frame = frame.add_child(&truncated, self.aggregator.frame_id(&truncated), count);
+            depth += 1;
+        }
+        for (f, id) in self.aggregator.aggregate(stack_trace) {
+            frame = frame.add_child(f, id, count);
+            depth += 1;
         }
         frame.add_leaf(count);
-        self.depth = self.depth.max(stack_trace.frames.len());
+        self.depth = self.depth.max(depth);
     }
 }
 
-#[derive(Hash, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(Clone, Hash, Eq, PartialEq, Ord, PartialOrd)]
 pub struct FrameId {
     pub name: String,
     category: FrameCategory,
 }
 
-#[derive(Hash, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(Clone, Copy, Hash, Eq, PartialEq, Ord, PartialOrd)]
 pub enum FrameCategory {
     Native,
     Java,
@@ -82,11 +359,11 @@ pub enum FrameCategory {
 pub struct Frame {
     base_type: FrameType,
     pub children: BTreeMap<FrameId, Frame>,
-    pub total_count: usize,
-    pub self_count: usize,
-    pub inlined_count: usize,
-    pub c1_count: usize,
-    pub interpreted_count: usize,
+    pub total_count: u64,
+    pub self_count: u64,
+    pub inlined_count: u64,
+    pub c1_count: u64,
+    pub interpreted_count: u64,
 }
 
 impl Frame {
@@ -102,10 +379,9 @@ impl Frame {
         }
     }
 
-    pub fn add_child(&mut self, frame: &StackFrame, count: usize) -> &mut Frame {
-        self.total_count += count;
-
-        let (base_type, category) = match frame.frame_type {
+    /// Base frame type and the category of the frame type
+    fn categorize(frame_type: FrameType) -> (FrameType, FrameCategory) {
+        match frame_type {
             FrameType::Interpreted
             | FrameType::JitCompiled
             | FrameType::Inlined
@@ -113,13 +389,13 @@ impl Frame {
             FrameType::Cpp => (FrameType::Cpp, FrameCategory::Native),
             FrameType::Kernel => (FrameType::Kernel, FrameCategory::Kernel),
             _ => (FrameType::Native, FrameCategory::Native),
-        };
+        }
+    }
 
-        let id = FrameId {
-            name: frame.name().to_string(),
-            category,
-        };
+    pub fn add_child(&mut self, frame: &StackFrame, id: FrameId, count: u64) -> &mut Frame {
+        self.total_count += count;
 
+        let (base_type, _) = Self::categorize(frame.frame_type);
         let child = self.children.entry(id).or_insert_with(|| Frame {
             base_type,
             ..Default::default()
@@ -135,8 +411,318 @@ impl Frame {
         child
     }
 
-    pub fn add_leaf(&mut self, count: usize) {
+    pub fn add_leaf(&mut self, count: u64) {
         self.total_count += count;
         self.self_count += count;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::execution_sample::Filter;
+    use crate::flame_graph::{AggregationConfig, AllocationWeight, FlameGraph, Granularity};
+    use crate::profile::{ContentionKind, FrameType, StackFrame, StackTrace, ThreadState};
+    use crate::test_util::load;
+
+    #[test]
+    fn test_signature() {
+        let trace = |descriptor: &str| StackTrace {
+            frames: vec![StackFrame::new(
+                "java/io/OutputStream".to_string(),
+                "write".to_string(),
+                FrameType::JitCompiled,
+                0,
+            )
+            .with_method_signature(descriptor.to_string(), 0x0001)],
+            truncated: false,
+        };
+
+        let mut flame = FlameGraph::new();
+        flame.add_sample(&trace("([B)V"), 1);
+        flame.add_sample(&trace("(Ljava/nio/ByteBuffer;)V"), 2);
+        assert_eq!(flame.to_folded(), "java/io/OutputStream.write_[j] 3\n");
+
+        let mut flame = FlameGraph::with_config(&AggregationConfig {
+            signature: true,
+            ..Default::default()
+        });
+        flame.add_sample(&trace("([B)V"), 1);
+        flame.add_sample(&trace("(Ljava/nio/ByteBuffer;)V"), 2);
+        assert_eq!(
+            flame.to_folded(),
+            "java/io/OutputStream.write(byte[])_[j] 1\n\
+             java/io/OutputStream.write(java.nio.ByteBuffer)_[j] 2\n"
+        );
+    }
+
+    #[test]
+    fn test_granularity() {
+        let frame = |type_name: &str, method_name: &str, line_number| {
+            StackFrame::new(
+                type_name.to_string(),
+                method_name.to_string(),
+                FrameType::JitCompiled,
+                line_number,
+            )
+        };
+        // leaf first
+        let traces = [
+            StackTrace {
+                frames: vec![
+                    frame("com/example/Bar", "c", 5),
+                    frame("com/example/Foo", "b", 20),
+                    frame("com/example/Foo", "a", 10),
+                    frame("java/lang/Thread", "run", 1),
+                ],
+                truncated: false,
+            },
+            StackTrace {
+                frames: vec![
+                    frame("com/example/Foo", "b", 20),
+                    frame("com/example/Foo", "a", 11),
+                    frame("java/lang/Thread", "run", 1),
+                    StackFrame::new(
+                        "".to_string(),
+                        "start_thread".to_string(),
+                        FrameType::Native,
+                        0,
+                    ),
+                ],
+                truncated: false,
+            },
+        ];
+        let folded = |granularity| {
+            let mut flame = FlameGraph::with_config(&AggregationConfig {
+                granularity,
+                ..Default::default()
+            });
+            for trace in traces.iter() {
+                flame.add_sample(trace, 1);
+            }
+            flame.to_folded()
+        };
+
+        assert_eq!(
+            folded(Granularity::Line),
+            "java/lang/Thread.run:1_[j];com/example/Foo.a:10_[j];com/example/Foo.b:20_[j];com/example/Bar.c:5_[j] 1\n\
+             start_thread;java/lang/Thread.run:1_[j];com/example/Foo.a:11_[j];com/example/Foo.b:20_[j] 1\n"
+        );
+        assert_eq!(
+            folded(Granularity::Method),
+            "java/lang/Thread.run_[j];com/example/Foo.a_[j];com/example/Foo.b_[j];com/example/Bar.c_[j] 1\n\
+             start_thread;java/lang/Thread.run_[j];com/example/Foo.a_[j];com/example/Foo.b_[j] 1\n"
+        );
+        assert_eq!(
+            folded(Granularity::Class),
+            "java/lang/Thread_[j];com/example/Foo_[j];com/example/Bar_[j] 1\n\
+             start_thread;java/lang/Thread_[j];com/example/Foo_[j] 1\n"
+        );
+        assert_eq!(
+            folded(Granularity::Package),
+            "java/lang_[j];com/example_[j] 1\n\
+             start_thread;java/lang_[j];com/example_[j] 1\n"
+        );
+
+        // the suffix of hidden classes isn't a package even without normalization
+        let mut flame = FlameGraph::with_config(&AggregationConfig {
+            granularity: Granularity::Package,
+            ..Default::default()
+        });
+        flame.add_sample(
+            &StackTrace {
+                frames: vec![
+                    frame("com/example/Foo$$Lambda$14/0x0000000800c1a440", "run", 0),
+                    frame("java/lang/Thread", "run", 1),
+                ],
+                truncated: false,
+            },
+            1,
+        );
+        assert_eq!(flame.to_folded(), "java/lang_[j];com/example_[j] 1\n");
+    }
+
+    #[test]
+    fn test_truncated() {
+        let trace = |truncated| StackTrace {
+            frames: vec![StackFrame::new(
+                "com/example/Foo".to_string(),
+                "work".to_string(),
+                FrameType::JitCompiled,
+                0,
+            )],
+            truncated,
+        };
+
+        let mut flame = FlameGraph::new();
+        flame.add_sample(&trace(false), 1);
+        flame.add_sample(&trace(true), 2);
+        assert_eq!(
+            flame.to_folded(),
+            "[truncated];com/example/Foo.work_[j] 2\n\
+             com/example/Foo.work_[j] 1\n"
+        );
+        assert_eq!(flame.depth, 2);
+    }
+
+    #[test]
+    fn test_from_allocation_sample() {
+        let profile = load("hotspot-allocation.jfr");
+        let samples = profile
+            .per_thread_allocation_samples
+            .values()
+            .flatten()
+            .collect::<Vec<_>>();
+
+        let config = AggregationConfig::default();
+        let bytes = FlameGraph::from_allocation_sample(&profile, AllocationWeight::Bytes, &config);
+        assert_eq!(
+            bytes.root.total_count,
+            samples
+                .iter()
+                .map(|s| s.allocation_size as u64)
+                .sum::<u64>()
+        );
+        let count = FlameGraph::from_allocation_sample(&profile, AllocationWeight::Count, &config);
+        assert_eq!(count.root.total_count, samples.len() as u64);
+
+        // the allocated class is the leaf frame, marked as kernel if it's outside of TLAB
+        let outside_tlab = bytes
+            .to_folded()
+            .lines()
+            .filter_map(|l| l.rsplit_once(' '))
+            .filter(|(stack, _)| stack.ends_with(";[B_[k]"))
+            .map(|(_, v)| v.parse::<u64>().unwrap())
+            .sum::<u64>();
+        assert_eq!(
+            outside_tlab,
+            samples
+                .iter()
+                .filter(|s| s.outside_tlab)
+                .map(|s| s.allocation_size as u64)
+                .sum::<u64>()
+        );
+    }
+
+    #[test]
+    fn test_from_contention_sample() {
+        let profile = load("hotspot-contention.jfr");
+        let flame = FlameGraph::from_contention_sample(&profile, &AggregationConfig::default());
+        assert_eq!(
+            flame.root.total_count,
+            profile
+                .per_thread_contention_samples
+                .values()
+                .flatten()
+                .map(|s| s.duration_nanos as u64)
+                .sum::<u64>()
+        );
+
+        // the monitor class is added as the leaf frame only when it's known
+        let folded = flame.to_folded();
+        let with_class = folded
+            .lines()
+            .filter(|l| l.contains(";java/lang/Object_[k] "))
+            .count();
+        assert!(0 < with_class && with_class < folded.lines().count());
+    }
+
+    #[test]
+    fn test_filter_contention_sample() {
+        let mut profile = load("hotspot-contention.jfr");
+        let config = AggregationConfig::default();
+        let duration_of = |kind| {
+            profile
+                .per_thread_contention_samples
+                .values()
+                .flatten()
+                .filter(|s| s.kind == kind)
+                .map(|s| s.duration_nanos as u64)
+                .sum::<u64>()
+        };
+        let parked = duration_of(ContentionKind::ThreadPark);
+        let total = parked + duration_of(ContentionKind::MonitorEnter);
+
+        let mut total_count = |filter| {
+            profile.apply_filter(filter).unwrap();
+            FlameGraph::from_contention_sample(&profile, &config)
+                .root
+                .total_count
+        };
+        let query = |query: &str| Filter {
+            query: Some(query.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(total_count(query("contains:LockSupport.park")), parked);
+        assert_eq!(
+            total_count(query("NOT contains:LockSupport.park")),
+            total - parked
+        );
+        assert_eq!(total_count(query("thread:no-such-thread")), 0);
+        // contention samples don't have the thread state
+        assert_eq!(total_count(query("state:BLOCKED")), 0);
+        assert_eq!(total_count(query("NOT state:BLOCKED")), total);
+        assert_eq!(
+            total_count(Filter {
+                states: Some(vec![ThreadState::Blocked]),
+                ..Default::default()
+            }),
+            0
+        );
+
+        // the previous filter is kept if the query is invalid
+        total_count(query("contains:LockSupport.park"));
+        assert!(profile.apply_filter(query("contains:")).is_err());
+        assert_eq!(
+            FlameGraph::from_contention_sample(&profile, &config)
+                .root
+                .total_count,
+            parked
+        );
+    }
+
+    #[test]
+    fn test_filter_allocation_sample() {
+        let mut profile = load("hotspot-allocation.jfr");
+        let config = AggregationConfig::default();
+        let expected = |f: &dyn Fn(&str, &StackTrace) -> bool| {
+            let profile = &profile;
+            profile
+                .filtered_threads()
+                .iter()
+                .flat_map(|t| {
+                    profile.per_thread_allocation_samples[&t.id]
+                        .iter()
+                        .filter(move |s| f(&t.name, &profile.stack_trace_pool[&s.stack_trace_key]))
+                })
+                .count() as u64
+        };
+        let in_work = expected(&|_, trace| {
+            trace
+                .frames
+                .iter()
+                .any(|f| f.name().contains("Fixture.work"))
+        });
+        let thread_name = profile.filtered_threads()[0].name.clone();
+        let on_thread = expected(&|name, _| name == thread_name);
+        let total = expected(&|_, _| true);
+        assert!(0 < in_work && in_work < total);
+        assert!(0 < on_thread && on_thread < total);
+
+        let mut total_count = |query: String| {
+            profile
+                .apply_filter(Filter {
+                    query: Some(query),
+                    ..Default::default()
+                })
+                .unwrap();
+            FlameGraph::from_allocation_sample(&profile, AllocationWeight::Count, &config)
+                .root
+                .total_count
+        };
+        assert_eq!(total_count("contains:Fixture.work".to_string()), in_work);
+        assert_eq!(
+            total_count(format!("thread:\"{}\"", thread_name)),
+            on_thread
+        );
+    }
+}
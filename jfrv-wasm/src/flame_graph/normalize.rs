@@ -0,0 +1,214 @@
+//! Normalization of synthetic class names generated at runtime, whose suffixes differ
+//! across JVM restarts and would fragment the flame graph otherwise.
+//! Should not contain any wasm dependencies.
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+#[cfg(target_arch = "wasm32")]
+use tsify::Tsify;
+
+/// Which kind of synthetic class names to normalize
+#[derive(Copy, Clone, Default, Deserialize, Serialize)]
+#[cfg_attr(target_arch = "wasm32", derive(Tsify))]
+#[cfg_attr(target_arch = "wasm32", tsify(into_wasm_abi, from_wasm_abi))]
+#[serde(rename_all = "camelCase")]
+pub struct NormalizationConfig {
+    /// `Foo$$Lambda$1234/0x0000000800c1a440` => `Foo$$Lambda`
+    pub lambda: bool,
+    /// `Foo/0x0000000800c1a440` => `Foo`
+    pub hidden_class: bool,
+    /// `jdk/proxy2/$Proxy123` => `jdk/proxy/$Proxy`
+    pub proxy: bool,
+    /// `Foo$$EnhancerBySpringCGLIB$$1a2b3c4d` => `Foo$$EnhancerBySpringCGLIB`,
+    /// `Foo$ByteBuddy$abc123` => `Foo$ByteBuddy`
+    pub generated_class: bool,
+}
+
+/// Compiled normalization rules
+#[derive(Default)]
+pub struct Normalizer {
+    rules: Vec<(Regex, &'static str)>,
+}
+
+impl Normalizer {
+    pub fn new(config: &NormalizationConfig) -> Self {
+        let mut rules = vec![];
+        // lambda should precede hidden class because lambdas are hidden classes since JDK 15
+        if config.lambda {
+            rules.push((r"\$\$Lambda(\$\d+)?([/+.]0x[0-9a-fA-F]+)?", "$$$$Lambda"));
+        }
+        if config.hidden_class {
+            rules.push((r"[/+.]0x[0-9a-fA-F]+$", ""));
+        }
+        if config.proxy {
+            rules.push((r"\$Proxy\d+", "$$Proxy"));
+            rules.push((r"\bjdk([/.])proxy\d+\b", "jdk${1}proxy"));
+        }
+        if config.generated_class {
+            rules.push((
+                r"(\$\$?(?:EnhancerBy\w*?CGLIB|FastClassBy\w*?CGLIB|SpringCGLIB|ByteBuddy|HibernateProxy|MockitoMock))\$\$?\w+",
+                "${1}",
+            ));
+        }
+
+        Self {
+            rules: rules
+                .into_iter()
+                .map(|(pattern, replacement)| (Regex::new(pattern).unwrap(), replacement))
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Normalize the type name. Borrowed if no rule matched.
+    pub fn normalize<'a>(&self, type_name: &'a str) -> Cow<'a, str> {
+        let mut result = Cow::Borrowed(type_name);
+        for (regex, replacement) in self.rules.iter() {
+            if let Cow::Owned(s) = regex.replace_all(result.as_ref(), *replacement) {
+                result = Cow::Owned(s);
+            }
+        }
+        result
+    }
+}
+
+/// Strip the `/0x...` suffix of hidden classes, which would be taken as a package otherwise
+pub fn strip_hidden_class_suffix(type_name: &str) -> &str {
+    match type_name.rsplit_once('/') {
+        Some((name, suffix))
+            if suffix.strip_prefix("0x").is_some_and(|hex| {
+                !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit())
+            }) =>
+        {
+            name
+        }
+        _ => type_name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::flame_graph::normalize::{NormalizationConfig, Normalizer};
+    use crate::flame_graph::{AggregationConfig, FlameGraph};
+    use crate::profile::{FrameType, StackFrame, StackTrace};
+    use std::borrow::Cow;
+
+    fn all() -> NormalizationConfig {
+        NormalizationConfig {
+            lambda: true,
+            hidden_class: true,
+            proxy: true,
+            generated_class: true,
+        }
+    }
+
+    #[test]
+    fn test_normalize() {
+        let normalizer = Normalizer::new(&all());
+        let cases = [
+            (
+                "com/example/Foo$$Lambda$1234/0x0000000800c1a440",
+                "com/example/Foo$$Lambda",
+            ),
+            (
+                "com/example/Foo$$Lambda+0x0000000800c1a440",
+                "com/example/Foo$$Lambda",
+            ),
+            ("com/example/Foo$$Lambda$56", "com/example/Foo$$Lambda"),
+            ("com/example/Foo/0x0000000800c1a440", "com/example/Foo"),
+            ("com/sun/proxy/$Proxy123", "com/sun/proxy/$Proxy"),
+            ("jdk/proxy2/$Proxy45", "jdk/proxy/$Proxy"),
+            (
+                "com/example/Foo$$EnhancerBySpringCGLIB$$1a2b3c4d",
+                "com/example/Foo$$EnhancerBySpringCGLIB",
+            ),
+            (
+                "com/example/Foo$$FastClassBySpringCGLIB$$1a2b3c4d",
+                "com/example/Foo$$FastClassBySpringCGLIB",
+            ),
+            (
+                "com/example/Foo$$SpringCGLIB$$0",
+                "com/example/Foo$$SpringCGLIB",
+            ),
+            (
+                "com/example/Foo$ByteBuddy$abc123",
+                "com/example/Foo$ByteBuddy",
+            ),
+            (
+                "com/example/Foo$MockitoMock$1234567",
+                "com/example/Foo$MockitoMock",
+            ),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(normalizer.normalize(input), expected, "{}", input);
+        }
+
+        assert!(matches!(
+            normalizer.normalize("java/lang/Thread"),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn test_rule_toggle() {
+        let normalizer = Normalizer::new(&NormalizationConfig {
+            lambda: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            normalizer.normalize("Foo$$Lambda$1/0x0000000800c1a440"),
+            "Foo$$Lambda"
+        );
+        assert_eq!(normalizer.normalize("$Proxy12"), "$Proxy12");
+        assert_eq!(
+            normalizer.normalize("Foo/0x0000000800c1a440"),
+            "Foo/0x0000000800c1a440"
+        );
+
+        assert!(Normalizer::new(&NormalizationConfig::default()).is_empty());
+    }
+
+    #[test]
+    fn test_flame_graph() {
+        let trace = |type_name: &str| StackTrace {
+            frames: vec![
+                StackFrame::new(
+                    type_name.to_string(),
+                    "apply".to_string(),
+                    FrameType::JitCompiled,
+                    0,
+                ),
+                StackFrame::new(
+                    "Main".to_string(),
+                    "main".to_string(),
+                    FrameType::JitCompiled,
+                    10,
+                ),
+            ],
+            truncated: false,
+        };
+
+        let mut flame = FlameGraph::with_config(&AggregationConfig {
+            normalization: all(),
+            ..Default::default()
+        });
+        flame.add_sample(&trace("Foo$$Lambda$1/0x0000000800c1a440"), 1);
+        flame.add_sample(&trace("Foo$$Lambda$2/0x0000000800c1b000"), 2);
+        assert_eq!(
+            flame.to_folded(),
+            "Main.main:10_[j];Foo$$Lambda.apply_[j] 3\n"
+        );
+
+        let mut flame = FlameGraph::new();
+        flame.add_sample(&trace("Foo$$Lambda$1/0x0000000800c1a440"), 1);
+        flame.add_sample(&trace("Foo$$Lambda$2/0x0000000800c1b000"), 2);
+        assert_eq!(
+            flame.root.children.values().next().unwrap().children.len(),
+            2
+        );
+    }
+}
@@ -1,6 +1,7 @@
+use crate::flame_graph::AggregationConfig;
 use crate::profile::FrameType;
 use crate::web::{Canvas, Document};
-use crate::{flame_graph, Result};
+use crate::{flame_graph, pprof, Result};
 use num_format::{Locale, ToFormattedString};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -18,6 +19,8 @@ pub struct FlameGraphConfig {
     pub status_id: String,
     pub font: String,
     pub color_palette: HashMap<FrameType, FrameColorConfig>,
+    #[serde(default)]
+    pub aggregation: AggregationConfig,
 }
 
 #[derive(Default, Deserialize, Serialize, Tsify)]
@@ -70,6 +73,20 @@ impl FlameGraphRenderer {
         })
     }
 
+    /// Build a flame graph from folded stacks, i.e. `frame;frame;frame count` lines.
+    pub fn parse_folded(input: &str, config: FlameGraphConfig) -> Result<FlameGraph> {
+        let flame_graph = flame_graph::FlameGraph::from_folded(input)
+            .map_err(|e| JsValue::from_str(e.to_string().as_str()))?;
+        Ok(FlameGraph::from(&flame_graph, &config.color_palette))
+    }
+
+    /// Build a flame graph from (possibly gzipped) pprof profile.proto.
+    pub fn parse_pprof(bytes: Vec<u8>, config: FlameGraphConfig) -> Result<FlameGraph> {
+        let flame_graph =
+            pprof::import(bytes).map_err(|e| JsValue::from_str(e.to_string().as_str()))?;
+        Ok(FlameGraph::from(&flame_graph, &config.color_palette))
+    }
+
     pub fn render(&self) -> Result<()> {
         self.chart.raw.style().set_property(
             "height",
@@ -320,7 +337,7 @@ impl FlameGraph {
     fn traverse_frame(
         title: &str,
         frame: &flame_graph::Frame,
-        x: usize,
+        x: u64,
         level: usize,
         levels: &mut Vec<Level>,
         color_palette: &HashMap<FrameType, FrameColorConfig>,
@@ -365,7 +382,7 @@ impl FlameGraph {
         }
     }
 
-    fn nonzero(n: usize) -> Option<usize> {
+    fn nonzero(n: u64) -> Option<u64> {
         if n > 0 {
             Some(n)
         } else {
@@ -398,8 +415,8 @@ pub struct Level {
 #[tsify(into_wasm_abi, from_wasm_abi)]
 #[serde(rename_all = "camelCase")]
 pub struct Frame {
-    pub x: usize,
-    pub count: usize,
+    pub x: u64,
+    pub count: u64,
     pub frame_type: FrameType,
     pub frame_color_hex: String,
     pub title: String,
@@ -410,8 +427,8 @@ pub struct Frame {
 #[tsify(into_wasm_abi, from_wasm_abi)]
 #[serde(rename_all = "camelCase")]
 pub struct FrameDetail {
-    interpreted_count: Option<usize>,
-    c1_compiled_count: Option<usize>,
-    inlined_count: Option<usize>,
+    interpreted_count: Option<u64>,
+    c1_compiled_count: Option<u64>,
+    inlined_count: Option<u64>,
     description: String,
 }
@@ -4,9 +4,10 @@
 pub mod render;
 
 use crate::profile::{FrameType, OffCpu, StackFrame, StackTrace, Thread, ThreadState};
-use crate::TimeInterval;
+use crate::time::TimeZoneConfig;
+use crate::{snapshot, TimeInterval};
 use anyhow::{anyhow, Result};
-use chrono::{Local, NaiveDateTime, TimeZone};
+use chrono::NaiveDateTime;
 use log::info;
 use regex::Regex;
 use rustc_hash::{FxHashMap, FxHashSet};
@@ -17,17 +18,23 @@ use std::io::{BufRead, BufReader, Cursor};
 use tsify::Tsify;
 
 /// Conditions to filter data
-#[derive(Default, Deserialize, Serialize)]
+#[derive(Clone, Default, Deserialize, Serialize)]
 #[cfg_attr(target_arch = "wasm32", derive(Tsify))]
 #[cfg_attr(target_arch = "wasm32", tsify(into_wasm_abi, from_wasm_abi))]
 #[serde(rename_all = "camelCase")]
 pub struct JbmFilter {
     pub thread_name_regex: Option<String>,
+    /// Show only off-cpu samples overlapping with the range if specified
+    pub start_nanos: Option<i64>,
+    pub end_nanos: Option<i64>,
 }
 
-#[derive(Default)]
+/// Filtered fields are derived from the filter, so they are rebuilt on restoring a snapshot
+#[derive(Default, Deserialize, Serialize)]
 pub struct Profile {
     threads: Vec<Thread>,
+    filter: JbmFilter,
+    #[serde(skip)]
     filtered_threads: Vec<Thread>,
     pub stack_trace_pool: FxHashMap<i32, StackTrace>,
     pub per_thread_samples: FxHashMap<i64, Vec<OffCpu>>,
@@ -35,7 +42,9 @@ pub struct Profile {
 }
 
 impl Profile {
-    pub fn load(&mut self, bytes: Vec<u8>) -> Result<()> {
+    /// Load the log whose timestamps are wall-clock times in the given time zone
+    pub fn load(&mut self, bytes: Vec<u8>, time_zone: &TimeZoneConfig) -> Result<()> {
+        let zone = time_zone.resolve()?;
         let reader = BufReader::new(Cursor::new(bytes));
         let sample_start = Regex::new(
             r"^=== ([-: 0-9]+)\.([0-9]+) PID: [0-9]+, TID: ([0-9]+) \(([^),]*)\), DURATION: ([0-9]+) us",
@@ -69,26 +78,27 @@ impl Profile {
                 let millis_fragment = &captures[2].parse::<i64>()?;
                 let tid = captures[3].parse::<i64>()?;
                 let thread_name = &captures[4];
-                let duration: i64 = captures[5].parse::<i64>()? / 1000;
+                let duration_nanos: i64 = captures[5].parse::<i64>()? * 1000;
 
+                // jvm-blocking-monitor doesn't tell thread lifetime, so we just identify by tid
                 let t = Thread {
-                    name: format!("{} [tid=0x{:x}]", thread_name, tid),
+                    id: tid,
                     os_thread_id: tid,
+                    java_thread_id: 0,
+                    name: format!("{} [tid=0x{:x}]", thread_name, tid),
                 };
                 threads.insert(t);
-                let timestamp = Local
-                    .from_local_datetime(&NaiveDateTime::parse_from_str(ts, "%Y-%m-%d %H:%M:%S")?)
-                    .earliest()
+                let timestamp = zone
+                    .to_timestamp_millis(&NaiveDateTime::parse_from_str(ts, "%Y-%m-%d %H:%M:%S")?)
                     .ok_or_else(|| anyhow!("failed to parse timestamp"))?
-                    .timestamp_millis()
-                    + *millis_fragment
-                    - duration;
+                    + *millis_fragment;
+                let timestamp_nanos = timestamp * 1_000_000 - duration_nanos;
 
-                interval.start_millis = interval.start_millis.min(timestamp);
-                interval.end_millis = interval.end_millis.max(timestamp + duration);
+                interval.start_nanos = interval.start_nanos.min(timestamp_nanos);
+                interval.end_nanos = interval.end_nanos.max(timestamp_nanos + duration_nanos);
                 parsing_sample = Some(ParsingSample {
-                    timestamp,
-                    duration,
+                    timestamp_nanos,
+                    duration_nanos,
                     thread_id: tid,
                     state: ThreadState::Unknown,
                     frames: vec![],
@@ -104,12 +114,10 @@ impl Profile {
                     }
                 }
                 if let Some(sample) = &mut parsing_sample {
-                    sample.frames.push(StackFrame::new(
-                        "".to_string(),
-                        frame.to_string(),
-                        FrameType::Unknown,
-                        0,
-                    ));
+                    sample.frames.push(
+                        StackFrame::new("".to_string(), frame.to_string(), FrameType::Unknown, 0)
+                            .demangled(),
+                    );
                 }
                 continue;
             }
@@ -132,10 +140,11 @@ impl Profile {
             inverted.insert(v, k);
         }
         for (_, samples) in per_thread_samples.iter_mut() {
-            samples.sort_by_key(|s| s.timestamp);
+            samples.sort_by_key(|s| s.timestamp_nanos);
         }
 
         self.threads = threads;
+        self.filter = JbmFilter::default();
         self.filtered_threads = self.threads.to_vec();
         self.stack_trace_pool = inverted;
         self.per_thread_samples = per_thread_samples;
@@ -152,6 +161,7 @@ impl Profile {
     ) {
         let stack_trace = StackTrace {
             frames: sample.frames,
+            truncated: false,
         };
         let id = match stack_trace_pool.entry(stack_trace) {
             Entry::Occupied(e) => *e.get(),
@@ -161,12 +171,10 @@ impl Profile {
                 *id
             }
         };
-        let samples = per_thread_samples
-            .entry(sample.thread_id)
-            .or_insert_with(Vec::new);
+        let samples = per_thread_samples.entry(sample.thread_id).or_default();
         samples.push(OffCpu {
-            timestamp: sample.timestamp,
-            duration_millis: sample.duration,
+            timestamp_nanos: sample.timestamp_nanos,
+            duration_nanos: sample.duration_nanos,
             state: sample.state,
             stack_trace_key: id,
         });
@@ -176,7 +184,42 @@ impl Profile {
         &self.filtered_threads
     }
 
+    /// The filter which is currently applied
+    pub fn filter(&self) -> &JbmFilter {
+        &self.filter
+    }
+
+    /// Serialize the profile along with the active filter into a snapshot
+    pub fn snapshot(&self) -> Result<Vec<u8>> {
+        snapshot::save(snapshot::Kind::Jbm, self)
+    }
+
+    /// Restore the profile from the snapshot then re-apply the filter
+    pub fn restore(bytes: &[u8]) -> Result<Self> {
+        let mut profile: Self = snapshot::restore(snapshot::Kind::Jbm, bytes)?;
+        profile.apply_filter(profile.filter.clone())?;
+        Ok(profile)
+    }
+
+    /// Whether the off-cpu interval overlaps with the time range of the filter
+    pub fn is_valid_sample(&self, sample: &OffCpu) -> bool {
+        self.filter
+            .start_nanos
+            .map(|start| start <= sample.timestamp_nanos + sample.duration_nanos)
+            .unwrap_or(true)
+            && self
+                .filter
+                .end_nanos
+                .map(|end| sample.timestamp_nanos <= end)
+                .unwrap_or(true)
+    }
+
     pub fn apply_filter(&mut self, filter: JbmFilter) -> Result<()> {
+        if let (Some(start), Some(end)) = (filter.start_nanos, filter.end_nanos) {
+            if start > end {
+                return Err(anyhow!("Invalid time range: {} > {}", start, end));
+            }
+        }
         if let Some(regex) = &filter.thread_name_regex {
             let regex = Regex::new(regex.as_str())?;
             info!("regex: {:?}", regex);
@@ -190,13 +233,14 @@ impl Profile {
         } else {
             self.filtered_threads = self.threads.to_vec();
         }
+        self.filter = filter;
         Ok(())
     }
 }
 
 struct ParsingSample {
-    timestamp: i64,
-    duration: i64,
+    timestamp_nanos: i64,
+    duration_nanos: i64,
     thread_id: i64,
     state: ThreadState,
     frames: Vec<StackFrame>,
@@ -204,7 +248,9 @@ struct ParsingSample {
 
 #[cfg(test)]
 mod tests {
-    use crate::jbm::Profile;
+    use crate::jbm::{JbmFilter, Profile};
+    use crate::test_util::read;
+    use crate::time::TimeZoneConfig;
 
     use std::fs::File;
     use std::io::Read;
@@ -217,7 +263,109 @@ mod tests {
         File::open(path).unwrap().read_to_end(&mut bytes).unwrap();
 
         let mut profile = Profile::default();
-        assert!(profile.load(bytes).is_ok());
+        assert!(profile.load(bytes, &TimeZoneConfig::Utc).is_ok());
         assert_eq!(2, profile.threads.len());
+        // durations are reported in microseconds
+        assert!(profile
+            .per_thread_samples
+            .values()
+            .flatten()
+            .any(|s| s.duration_nanos == 50_094_123_000));
+        assert!(profile.per_thread_samples.values().flatten().all(|s| {
+            profile.interval.start_nanos <= s.timestamp_nanos
+                && s.timestamp_nanos + s.duration_nanos <= profile.interval.end_nanos
+        }));
+    }
+
+    #[test]
+    fn test_load_time_zone() {
+        let bytes = read("jbm.log");
+
+        let mut utc = Profile::default();
+        utc.load(bytes.clone(), &TimeZoneConfig::Utc).unwrap();
+        let mut jst = Profile::default();
+        jst.load(
+            bytes,
+            &TimeZoneConfig::Iana {
+                name: "Asia/Tokyo".to_string(),
+            },
+        )
+        .unwrap();
+        // the first sample ends at 2022-09-23 05:47:15.693 in the log
+        assert_eq!(
+            utc.interval.start_nanos,
+            1_663_912_035_693_000_000 - 50_094_123_000
+        );
+        assert_eq!(
+            utc.interval.start_nanos - jst.interval.start_nanos,
+            9 * 3600 * 1_000_000_000
+        );
+    }
+
+    #[test]
+    fn test_filter_time_range() {
+        let bytes = read("jbm.log");
+
+        let mut profile = Profile::default();
+        profile.load(bytes, &TimeZoneConfig::Utc).unwrap();
+        let sample = profile
+            .per_thread_samples
+            .values()
+            .flatten()
+            .next()
+            .unwrap();
+        let (start, end) = (
+            sample.timestamp_nanos,
+            sample.timestamp_nanos + sample.duration_nanos,
+        );
+
+        // off-cpu intervals partially overlapping with the range are kept
+        profile
+            .apply_filter(JbmFilter {
+                start_nanos: Some(end),
+                ..Default::default()
+            })
+            .unwrap();
+        let sample = profile
+            .per_thread_samples
+            .values()
+            .flatten()
+            .next()
+            .unwrap();
+        assert!(profile.is_valid_sample(sample));
+        profile
+            .apply_filter(JbmFilter {
+                start_nanos: Some(end + 1),
+                ..Default::default()
+            })
+            .unwrap();
+        let sample = profile
+            .per_thread_samples
+            .values()
+            .flatten()
+            .next()
+            .unwrap();
+        assert!(!profile.is_valid_sample(sample));
+        profile
+            .apply_filter(JbmFilter {
+                end_nanos: Some(start - 1),
+                ..Default::default()
+            })
+            .unwrap();
+        let sample = profile
+            .per_thread_samples
+            .values()
+            .flatten()
+            .next()
+            .unwrap();
+        assert!(!profile.is_valid_sample(sample));
+
+        assert!(profile
+            .apply_filter(JbmFilter {
+                start_nanos: Some(end),
+                end_nanos: Some(start),
+                ..Default::default()
+            })
+            .is_err());
     }
 }
@@ -1,16 +1,19 @@
 //! jbm chart renderer.
 
 use crate::jbm::{JbmFilter, Profile};
+use crate::time::{TimeDisplayConfig, TimeFormatter, TimeZoneConfig};
 use crate::web::{Canvas, Document, Svg};
-use crate::Result;
-use chrono::{Duration, Local, NaiveDateTime, TimeZone};
+use crate::{trace_event, Result};
 use log::debug;
 
 use crate::profile::{StackTrace, ThreadState};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use tsify::Tsify;
 use wasm_bindgen::prelude::*;
 
+const NANOS_PER_HOUR: f64 = 3_600_000_000_000.0;
+
 #[derive(Default, Deserialize, Serialize, Tsify)]
 #[tsify(into_wasm_abi, from_wasm_abi)]
 #[serde(rename_all = "camelCase")]
@@ -56,9 +59,19 @@ pub struct JbmOverlayConfig {
 #[tsify(into_wasm_abi, from_wasm_abi)]
 #[serde(rename_all = "camelCase")]
 pub struct JbmThreadStateColorConfig {
-    pub state_runnable_rgb_hex: u32,
-    pub state_sleeping_rgb_hex: u32,
-    pub state_unknown_rgb_hex: u32,
+    /// Colors of the states. Falls back to the color of Unknown if absent
+    pub state_rgb_hex: HashMap<ThreadState, u32>,
+    pub state_hidden_rgb_hex: u32,
+}
+
+impl JbmThreadStateColorConfig {
+    fn state_color(&self, state: ThreadState) -> u32 {
+        self.state_rgb_hex
+            .get(&state)
+            .or_else(|| self.state_rgb_hex.get(&ThreadState::Unknown))
+            .cloned()
+            .unwrap_or_default()
+    }
 }
 
 /// State of the current rendered chart
@@ -83,6 +96,7 @@ pub struct JbmSampleInfo {
 #[wasm_bindgen]
 pub struct JbmRenderer {
     profile: Profile,
+    time_formatter: TimeFormatter,
     chart_config: JbmChartConfig,
     chart_state: JbmChartState,
     document: Document,
@@ -101,6 +115,7 @@ impl JbmRenderer {
         let document = Document::try_new()?;
         Ok(Self {
             profile: Profile::default(),
+            time_formatter: TimeFormatter::default(),
             chart_state: JbmChartState::default(),
             header: document.get_svg_by_id(chart_config.header_config.element_id.as_str())?,
             header_overlay: document
@@ -141,31 +156,22 @@ impl JbmRenderer {
         debug!("start draw frame");
 
         for (i, thread) in self.profile.filtered_threads().iter().enumerate() {
-            if let Some(samples) = self.profile.per_thread_samples.get(&thread.os_thread_id) {
+            if let Some(samples) = self.profile.per_thread_samples.get(&thread.id) {
                 let y = self.row_height() * i as f32
                     + (self.row_height()
                         - self.chart_config.sample_view_config.sample_render_height)
                         / 2.0;
                 for (_j, sample) in samples.iter().enumerate() {
                     let x = self.sample_view_width()
-                        * (sample.timestamp - self.profile.interval.start_millis) as f32
-                        / self.profile.interval.duration_millis() as f32;
-                    let color = match sample.state {
-                        ThreadState::Unknown => {
-                            self.chart_config
-                                .thread_state_color_config
-                                .state_unknown_rgb_hex
-                        }
-                        ThreadState::Runnable => {
-                            self.chart_config
-                                .thread_state_color_config
-                                .state_runnable_rgb_hex
-                        }
-                        ThreadState::Sleeping => {
-                            self.chart_config
-                                .thread_state_color_config
-                                .state_sleeping_rgb_hex
-                        }
+                        * self.profile.interval.elapsed_ratio(sample.timestamp_nanos) as f32;
+                    let color = if self.profile.is_valid_sample(sample) {
+                        self.chart_config
+                            .thread_state_color_config
+                            .state_color(sample.state)
+                    } else {
+                        self.chart_config
+                            .thread_state_color_config
+                            .state_hidden_rgb_hex
                     };
 
                     self.chart
@@ -175,7 +181,7 @@ impl JbmRenderer {
                         x as f64,
                         y as f64,
                         self.chart_config.sample_view_config.sample_width_per_hour as f64
-                            * (sample.duration_millis as f64 / 3600000.0),
+                            * (sample.duration_nanos as f64 / NANOS_PER_HOUR),
                         self.chart_config.sample_view_config.sample_render_height as f64,
                     );
                 }
@@ -243,11 +249,30 @@ impl JbmRenderer {
         Ok(())
     }
 
-    pub fn initialize(&mut self, bytes: Vec<u8>) -> Result<()> {
-        self.profile.load(bytes).map_err(Self::map_js_value)?;
+    /// Load the log whose timestamps are wall-clock times in the given time zone
+    pub fn initialize(&mut self, bytes: Vec<u8>, time_zone: TimeZoneConfig) -> Result<()> {
+        self.profile
+            .load(bytes, &time_zone)
+            .map_err(Self::map_js_value)?;
         Ok(())
     }
 
+    /// Restore the session from the snapshot taken by `snapshot`
+    pub fn restore(&mut self, bytes: Vec<u8>) -> Result<()> {
+        self.profile = Profile::restore(bytes.as_slice()).map_err(Self::map_js_value)?;
+        Ok(())
+    }
+
+    /// Snapshot of the loaded profile along with the current filter
+    pub fn snapshot(&self) -> Result<Vec<u8>> {
+        self.profile.snapshot().map_err(Self::map_js_value)
+    }
+
+    /// The filter which is currently applied, e.g. to fill the inputs after restoring a session
+    pub fn filter(&self) -> JbmFilter {
+        self.profile.filter().clone()
+    }
+
     pub fn apply_filter(&mut self, filter: JbmFilter) -> Result<()> {
         self.profile
             .apply_filter(filter)
@@ -255,6 +280,42 @@ impl JbmRenderer {
         self.render()
     }
 
+    /// Narrow the filter down to the time range between the x coordinates on the chart,
+    /// e.g. selected by dragging
+    pub fn apply_time_range(&mut self, start_x: f32, end_x: f32) -> Result<()> {
+        let mut filter = self.profile.filter().clone();
+        filter.start_nanos = Some(self.timestamp_at(start_x.min(end_x)));
+        filter.end_nanos = Some(self.timestamp_at(start_x.max(end_x)));
+        self.apply_filter(filter)
+    }
+
+    pub fn clear_time_range(&mut self) -> Result<()> {
+        let mut filter = self.profile.filter().clone();
+        filter.start_nanos = None;
+        filter.end_nanos = None;
+        self.apply_filter(filter)
+    }
+
+    /// Format the timestamp according to the time display config
+    pub fn format_timestamp(&self, timestamp_nanos: i64, fraction_digits: u32) -> String {
+        self.time_formatter.format(
+            timestamp_nanos,
+            self.profile.interval.start_nanos,
+            fraction_digits,
+        )
+    }
+
+    /// Change how timestamps are displayed
+    pub fn apply_time_display(&mut self, config: TimeDisplayConfig) -> Result<()> {
+        self.time_formatter = TimeFormatter::new(&config).map_err(Self::map_js_value)?;
+        Ok(())
+    }
+
+    /// Chrome trace events JSON of the filtered threads
+    pub fn trace_events(&self) -> Result<String> {
+        trace_event::export_jbm(&self.profile).map_err(Self::map_js_value)
+    }
+
     pub fn on_chart_mouse_move(&mut self, x: f32, y: f32) {
         self.on_mouse_move(Some(x), y)
     }
@@ -288,25 +349,14 @@ impl JbmRenderer {
                     .and_then(|s| self.profile.stack_trace_pool.get(&s.stack_trace_key))
                     .cloned();
                 match (thread, sample, stack_trace) {
-                    (Some(thread), Some(sample), Some(stack_trace)) => {
-                        let t = NaiveDateTime::from_timestamp(
-                            sample.timestamp / 1000,
-                            (sample.timestamp % 1000) as u32 * 1000000,
-                        );
-                        Some(JbmSampleInfo {
-                            stack_trace,
-                            duration_millis: sample.duration_millis,
-                            thread_name: thread.name.clone(),
-                            offcpu_start: Local
-                                .from_utc_datetime(&t)
-                                .format("%Y-%m-%d %H:%M:%S.%3f")
-                                .to_string(),
-                            offcpu_end: (Local.from_utc_datetime(&t)
-                                + Duration::milliseconds(sample.duration_millis))
-                            .format("%Y-%m-%d %H:%M:%S.%3f")
-                            .to_string(),
-                        })
-                    }
+                    (Some(thread), Some(sample), Some(stack_trace)) => Some(JbmSampleInfo {
+                        stack_trace,
+                        duration_millis: sample.duration_nanos / 1_000_000,
+                        thread_name: thread.name.clone(),
+                        offcpu_start: self.format_timestamp(sample.timestamp_nanos, 3),
+                        offcpu_end: self
+                            .format_timestamp(sample.timestamp_nanos + sample.duration_nanos, 3),
+                    }),
                     _ => None,
                 }
             }
@@ -320,28 +370,37 @@ impl JbmRenderer {
             .profile
             .filtered_threads()
             .get(thread_idx)
-            .map(|t| t.os_thread_id);
+            .map(|t| t.id);
 
         let mut highlighted_sample = None;
         if let Some(thread_id) = thread_id {
             if let (Some(samples), Some(x)) = (self.profile.per_thread_samples.get(&thread_id), x) {
                 // TODO: binary search
                 for (i, sample) in samples.iter().enumerate() {
+                    if !self.profile.is_valid_sample(sample) {
+                        continue;
+                    }
                     let sample_x = self.sample_view_width()
-                        * (sample.timestamp - self.profile.interval.start_millis) as f32
-                        / self.profile.interval.duration_millis() as f32;
+                        * self.profile.interval.elapsed_ratio(sample.timestamp_nanos) as f32;
                     let mut right_bound = sample_x
                         + self.chart_config.sample_view_config.sample_width_per_hour
-                            * (sample.duration_millis as f32 / 3600000.0);
-                    if let Some(next_sample) = samples.get(i + 1) {
+                            * (sample.duration_nanos as f64 / NANOS_PER_HOUR) as f32;
+                    // hidden samples aren't hoverable, so the sample extends to the next valid one
+                    if let Some(next_sample) = samples[i + 1..]
+                        .iter()
+                        .find(|s| self.profile.is_valid_sample(s))
+                    {
                         right_bound = self.sample_view_width()
-                            * (next_sample.timestamp - self.profile.interval.start_millis) as f32
-                            / self.profile.interval.duration_millis() as f32;
+                            * self
+                                .profile
+                                .interval
+                                .elapsed_ratio(next_sample.timestamp_nanos)
+                                as f32;
                     }
                     if sample_x <= x && x <= right_bound {
                         highlighted_sample = Some((
                             i,
-                            sample.duration_millis,
+                            sample.duration_nanos,
                             sample_x,
                             thread_idx as f32 * self.row_height(),
                         ));
@@ -379,7 +438,7 @@ impl JbmRenderer {
                     x as f64,
                     y as f64,
                     self.chart_config.sample_view_config.sample_width_per_hour as f64
-                        * (duration as f64 / 3600000.0),
+                        * (duration as f64 / NANOS_PER_HOUR),
                     h,
                 );
             }
@@ -391,7 +450,14 @@ impl JbmRenderer {
 
     fn sample_view_width(&self) -> f32 {
         self.chart_config.sample_view_config.sample_width_per_hour
-            * (self.profile.interval.duration_millis() as f32 / 1000.0 / 3600.0)
+            * (self.profile.interval.duration_nanos() as f64 / NANOS_PER_HOUR) as f32
+    }
+
+    /// The timestamp at the x coordinate on the chart
+    fn timestamp_at(&self, x: f32) -> i64 {
+        self.profile
+            .interval
+            .timestamp_at((x / self.sample_view_width()) as f64)
     }
 
     fn row_height(&self) -> f32 {
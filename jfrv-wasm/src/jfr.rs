@@ -0,0 +1,830 @@
+//! Writer of JFR format, which emits execution samples along with the constant pools they refer to.
+//! Thread lifetimes are emitted as well so that threads sharing the ids are still told apart.
+//! Frames are written as they are in the original recording, i.e. the aggregation config of
+//! flame graphs isn't applied, so that the output can be loaded and aggregated again.
+//! The output is a single chunk with self-contained metadata, so it can be read by jfrv and JMC.
+//! Should not contain any wasm dependencies.
+
+use crate::execution_sample::Profile;
+use crate::profile::{FrameType, SampleEventType, StackFrame, StackTrace, Thread};
+use anyhow::{anyhow, Result};
+use rustc_hash::FxHashMap;
+use std::hash::Hash;
+
+const MAGIC: &[u8] = b"FLR\0";
+const VERSION_MAJOR: i16 = 2;
+const VERSION_MINOR: i16 = 0;
+const HEADER_SIZE: usize = 68;
+const FEATURES_COMPRESSED_INTS: i32 = 1;
+/// Timestamps are written in nanoseconds
+const TICKS_PER_SECOND: i64 = 1_000_000_000;
+
+const EVENT_TYPE_METADATA: i64 = 0;
+const EVENT_TYPE_CONSTANT_POOL: i64 = 1;
+
+const STRING_ENCODING_NULL: u8 = 0;
+const STRING_ENCODING_EMPTY_STRING: u8 = 1;
+const STRING_ENCODING_UTF8_BYTE_ARRAY: u8 = 3;
+
+/// Class ids of the types declared in the metadata
+mod class_id {
+    pub const BOOLEAN: i64 = 4;
+    pub const INT: i64 = 8;
+    pub const LONG: i64 = 9;
+    pub const STRING: i64 = 20;
+    pub const THREAD: i64 = 21;
+    pub const CLASS: i64 = 22;
+    pub const FRAME_TYPE: i64 = 23;
+    pub const THREAD_STATE: i64 = 24;
+    pub const STACK_TRACE: i64 = 25;
+    pub const STACK_FRAME: i64 = 26;
+    pub const METHOD: i64 = 27;
+    pub const SYMBOL: i64 = 28;
+    pub const ANNOTATION_LABEL: i64 = 40;
+    pub const ANNOTATION_CATEGORY: i64 = 41;
+    pub const ANNOTATION_TIMESTAMP: i64 = 42;
+    pub const EXECUTION_SAMPLE: i64 = 100;
+    pub const NATIVE_METHOD_SAMPLE: i64 = 101;
+    pub const THREAD_START: i64 = 102;
+    pub const THREAD_END: i64 = 103;
+}
+
+/// Write the execution samples passing the current filter as a JFR file.
+/// Each thread starts at its first written sample and ends at the last one.
+pub fn write(profile: &Profile) -> Result<Vec<u8>> {
+    let mut pools = ConstantPools::default();
+    let mut events = ByteWriter::default();
+
+    // collect the samples first so that the chunk covers exactly the written samples
+    let mut per_thread_samples = vec![];
+    for thread in profile.filtered_threads() {
+        let samples = match profile.per_thread_samples.get(&thread.id) {
+            Some(samples) => samples,
+            None => continue,
+        };
+        let samples = samples
+            .iter()
+            .filter(|sample| profile.is_valid_sample(thread, *sample))
+            .filter_map(|sample| {
+                profile
+                    .stack_trace_pool
+                    .get(&sample.stack_trace_key)
+                    .map(|trace| (sample, trace))
+            })
+            .collect::<Vec<_>>();
+        if !samples.is_empty() {
+            per_thread_samples.push((thread, samples));
+        }
+    }
+    let timestamps = || {
+        per_thread_samples
+            .iter()
+            .flat_map(|(_, samples)| samples.iter().map(|(s, _)| s.timestamp_nanos))
+    };
+    let start_nanos = timestamps().min().unwrap_or(0);
+    let end_nanos = timestamps().max().unwrap_or(0);
+
+    for (thread, samples) in per_thread_samples.iter() {
+        let thread_index = pools.threads.intern(thread);
+        // samples are sorted by timestamp
+        let first_nanos = samples[0].0.timestamp_nanos;
+        let last_nanos = samples[samples.len() - 1].0.timestamp_nanos;
+        write_thread_lifetime(
+            &mut events,
+            class_id::THREAD_START,
+            first_nanos - start_nanos,
+            thread_index,
+        )?;
+
+        for (sample, trace) in samples.iter() {
+            let event_type = match sample.event_type {
+                SampleEventType::ExecutionSample => class_id::EXECUTION_SAMPLE,
+                SampleEventType::NativeMethodSample => class_id::NATIVE_METHOD_SAMPLE,
+            };
+            let stack_trace_index = pools.stack_trace(trace);
+            let state_index = pools.thread_states.intern(sample.state.name());
+
+            let start = events.begin_event(event_type);
+            events.write_long(sample.timestamp_nanos - start_nanos);
+            events.write_long(thread_index);
+            events.write_long(stack_trace_index);
+            events.write_long(state_index);
+            events.end_event(start)?;
+        }
+
+        write_thread_lifetime(
+            &mut events,
+            class_id::THREAD_END,
+            last_nanos - start_nanos,
+            thread_index,
+        )?;
+    }
+
+    let mut chunk = ByteWriter::default();
+    chunk.buf.resize(HEADER_SIZE, 0);
+    chunk.buf.extend_from_slice(events.buf.as_slice());
+    let constant_pool_offset = chunk.buf.len();
+    pools.write(&mut chunk)?;
+    let metadata_offset = chunk.buf.len();
+    write_metadata(&mut chunk)?;
+
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&VERSION_MAJOR.to_be_bytes());
+    header.extend_from_slice(&VERSION_MINOR.to_be_bytes());
+    header.extend_from_slice(&(chunk.buf.len() as i64).to_be_bytes());
+    header.extend_from_slice(&(constant_pool_offset as i64).to_be_bytes());
+    header.extend_from_slice(&(metadata_offset as i64).to_be_bytes());
+    header.extend_from_slice(&start_nanos.to_be_bytes());
+    header.extend_from_slice(&(end_nanos - start_nanos).to_be_bytes());
+    // start ticks
+    header.extend_from_slice(&0i64.to_be_bytes());
+    header.extend_from_slice(&TICKS_PER_SECOND.to_be_bytes());
+    header.extend_from_slice(&FEATURES_COMPRESSED_INTS.to_be_bytes());
+    chunk.buf[..HEADER_SIZE].copy_from_slice(header.as_slice());
+
+    Ok(chunk.buf)
+}
+
+/// Write jdk.ThreadStart or jdk.ThreadEnd event of the thread
+fn write_thread_lifetime(
+    w: &mut ByteWriter,
+    event_type: i64,
+    ticks: i64,
+    thread_index: i64,
+) -> Result<()> {
+    let start = w.begin_event(event_type);
+    w.write_long(ticks);
+    // event thread and the thread
+    w.write_long(thread_index);
+    w.write_long(thread_index);
+    w.end_event(start)
+}
+
+/// Byte buffer which writes integers in compressed (varint) encoding
+#[derive(Default)]
+struct ByteWriter {
+    buf: Vec<u8>,
+}
+
+impl ByteWriter {
+    /// Events are prefixed with the size, which is written as 4-byte padded varint
+    /// so that it can be filled after the event is written
+    const EVENT_SIZE_BYTES: usize = 4;
+
+    fn write_bool(&mut self, b: bool) {
+        self.buf.push(b as u8);
+    }
+
+    fn write_int(&mut self, i: i32) {
+        self.write_long(i as i64);
+    }
+
+    fn write_long(&mut self, l: i64) {
+        let mut v = l as u64;
+        for _ in 0..8 {
+            if v < 0x80 {
+                self.buf.push(v as u8);
+                return;
+            }
+            self.buf.push((v & 0x7f) as u8 | 0x80);
+            v >>= 7;
+        }
+        self.buf.push(v as u8);
+    }
+
+    fn write_string(&mut self, s: Option<&str>) {
+        match s {
+            None => self.buf.push(STRING_ENCODING_NULL),
+            Some("") => self.buf.push(STRING_ENCODING_EMPTY_STRING),
+            Some(s) => {
+                self.buf.push(STRING_ENCODING_UTF8_BYTE_ARRAY);
+                self.write_int(s.len() as i32);
+                self.buf.extend_from_slice(s.as_bytes());
+            }
+        }
+    }
+
+    fn begin_event(&mut self, event_type: i64) -> usize {
+        let start = self.buf.len();
+        self.buf.resize(start + Self::EVENT_SIZE_BYTES, 0);
+        self.write_long(event_type);
+        start
+    }
+
+    fn end_event(&mut self, start: usize) -> Result<()> {
+        let size = self.buf.len() - start;
+        if size >= 1 << (7 * Self::EVENT_SIZE_BYTES) {
+            return Err(anyhow!("Too large event: {} bytes", size));
+        }
+        for i in 0..Self::EVENT_SIZE_BYTES {
+            let b = ((size >> (7 * i)) & 0x7f) as u8;
+            self.buf[start + i] = if i + 1 < Self::EVENT_SIZE_BYTES {
+                b | 0x80
+            } else {
+                b
+            };
+        }
+        Ok(())
+    }
+}
+
+/// Assigns 1-origin indices to the values in insertion order
+struct Pool<K> {
+    indices: FxHashMap<K, i64>,
+    values: Vec<K>,
+}
+
+impl<K> Default for Pool<K> {
+    fn default() -> Self {
+        Self {
+            indices: FxHashMap::default(),
+            values: vec![],
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone> Pool<K> {
+    fn intern(&mut self, k: K) -> i64 {
+        if let Some(&i) = self.indices.get(&k) {
+            return i;
+        }
+        self.values.push(k.clone());
+        let i = self.values.len() as i64;
+        self.indices.insert(k, i);
+        i
+    }
+}
+
+#[derive(Clone, Copy, Hash, Eq, PartialEq)]
+struct MethodKey {
+    /// class index
+    class: i64,
+    /// symbol indices
+    name: i64,
+    descriptor: i64,
+    modifiers: i32,
+}
+
+#[derive(Default)]
+struct ConstantPools<'a> {
+    threads: Pool<&'a Thread>,
+    stack_traces: Pool<&'a StackTrace>,
+    methods: Pool<MethodKey>,
+    /// class name symbol index
+    classes: Pool<i64>,
+    symbols: Pool<&'a str>,
+    frame_types: Pool<FrameType>,
+    thread_states: Pool<&'static str>,
+}
+
+impl<'a> ConstantPools<'a> {
+    fn stack_trace(&mut self, trace: &'a StackTrace) -> i64 {
+        if let Some(&i) = self.stack_traces.indices.get(trace) {
+            return i;
+        }
+        for frame in trace.frames.iter() {
+            self.method(frame);
+            self.frame_types.intern(frame.frame_type);
+        }
+        self.stack_traces.intern(trace)
+    }
+
+    fn method(&mut self, frame: &'a StackFrame) -> i64 {
+        let key = self.method_key(frame);
+        self.methods.intern(key)
+    }
+
+    fn method_key(&mut self, frame: &'a StackFrame) -> MethodKey {
+        let class_name = self.symbols.intern(frame.type_name.as_str());
+        MethodKey {
+            class: self.classes.intern(class_name),
+            // write the raw symbol as it was in the original recording
+            name: self.symbols.intern(
+                frame
+                    .mangled_name
+                    .as_deref()
+                    .unwrap_or(frame.method_name.as_str()),
+            ),
+            descriptor: self.symbols.intern(frame.descriptor.as_str()),
+            modifiers: frame.modifiers,
+        }
+    }
+
+    fn write(&mut self, w: &mut ByteWriter) -> Result<()> {
+        let mut method_indices = FxHashMap::default();
+        for trace in self.stack_traces.values.clone() {
+            for frame in trace.frames.iter() {
+                let key = self.method_key(frame);
+                method_indices.insert(frame, self.methods.indices[&key]);
+            }
+        }
+
+        let start = w.begin_event(EVENT_TYPE_CONSTANT_POOL);
+        // start time, duration, delta to the next constant pool and flush
+        w.write_long(0);
+        w.write_long(0);
+        w.write_long(0);
+        w.write_bool(true);
+        w.write_int(7);
+
+        w.write_long(class_id::THREAD);
+        w.write_int(self.threads.values.len() as i32);
+        for (i, thread) in self.threads.values.iter().enumerate() {
+            w.write_long(i as i64 + 1);
+            w.write_string(Some(thread.name.as_str()));
+            w.write_long(thread.os_thread_id);
+            w.write_string(Some(thread.name.as_str()));
+            w.write_long(thread.java_thread_id);
+        }
+
+        w.write_long(class_id::STACK_TRACE);
+        w.write_int(self.stack_traces.values.len() as i32);
+        for (i, trace) in self.stack_traces.values.iter().enumerate() {
+            w.write_long(i as i64 + 1);
+            w.write_bool(trace.truncated);
+            w.write_int(trace.frames.len() as i32);
+            for frame in trace.frames.iter() {
+                w.write_long(method_indices[frame]);
+                w.write_int(frame.line_number);
+                // bytecode index
+                w.write_int(0);
+                w.write_long(self.frame_types.indices[&frame.frame_type]);
+            }
+        }
+
+        w.write_long(class_id::METHOD);
+        w.write_int(self.methods.values.len() as i32);
+        for (i, method) in self.methods.values.iter().enumerate() {
+            w.write_long(i as i64 + 1);
+            w.write_long(method.class);
+            w.write_long(method.name);
+            w.write_long(method.descriptor);
+            w.write_int(method.modifiers);
+            // hidden
+            w.write_bool(false);
+        }
+
+        w.write_long(class_id::CLASS);
+        w.write_int(self.classes.values.len() as i32);
+        for (i, name) in self.classes.values.iter().enumerate() {
+            w.write_long(i as i64 + 1);
+            w.write_long(*name);
+            // modifiers
+            w.write_int(0);
+        }
+
+        w.write_long(class_id::SYMBOL);
+        w.write_int(self.symbols.values.len() as i32);
+        for (i, symbol) in self.symbols.values.iter().enumerate() {
+            w.write_long(i as i64 + 1);
+            w.write_string(Some(symbol));
+        }
+
+        w.write_long(class_id::FRAME_TYPE);
+        w.write_int(self.frame_types.values.len() as i32);
+        for (i, frame_type) in self.frame_types.values.iter().enumerate() {
+            w.write_long(i as i64 + 1);
+            w.write_string(Some(frame_type.description()));
+        }
+
+        w.write_long(class_id::THREAD_STATE);
+        w.write_int(self.thread_states.values.len() as i32);
+        for (i, state) in self.thread_states.values.iter().enumerate() {
+            w.write_long(i as i64 + 1);
+            w.write_string(Some(state));
+        }
+
+        w.end_event(start)
+    }
+}
+
+/// Element of the metadata tree
+struct Element {
+    name: &'static str,
+    attributes: Vec<(String, String)>,
+    children: Vec<Element>,
+}
+
+impl Element {
+    fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            attributes: vec![],
+            children: vec![],
+        }
+    }
+
+    fn attribute(mut self, key: &str, value: impl ToString) -> Self {
+        self.attributes.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    fn child(mut self, child: Element) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    fn class(id: i64, name: &str) -> Self {
+        Self::new("class")
+            .attribute("id", id)
+            .attribute("name", name)
+    }
+
+    fn field(name: &str, class_id: i64, constant_pool: bool) -> Self {
+        let field = Self::new("field")
+            .attribute("name", name)
+            .attribute("class", class_id);
+        if constant_pool {
+            field.attribute("constantPool", true)
+        } else {
+            field
+        }
+    }
+
+    fn array_field(name: &str, class_id: i64) -> Self {
+        Self::field(name, class_id, false).attribute("dimension", 1)
+    }
+
+    fn annotation(class_id: i64, values: &[&str]) -> Self {
+        let annotation = Self::new("annotation").attribute("class", class_id);
+        match values {
+            [value] => annotation.attribute("value", value),
+            // array values are written as value-0, value-1, ...
+            values => values.iter().enumerate().fold(annotation, |a, (i, value)| {
+                a.attribute(format!("value-{}", i).as_str(), value)
+            }),
+        }
+    }
+
+    fn event(id: i64, name: &str, label: &str, category: &[&str]) -> Self {
+        Self::class(id, name)
+            .attribute("superType", "jdk.jfr.Event")
+            .child(Self::annotation(class_id::ANNOTATION_LABEL, &[label]))
+            .child(Self::annotation(class_id::ANNOTATION_CATEGORY, category))
+            .child(
+                Self::field("startTime", class_id::LONG, false)
+                    .child(Self::annotation(class_id::ANNOTATION_TIMESTAMP, &["TICKS"])),
+            )
+    }
+
+    fn sample_event(id: i64, name: &str, label: &str) -> Self {
+        Self::event(id, name, label, &["Java Virtual Machine", "Profiling"])
+            .child(Self::field("sampledThread", class_id::THREAD, true))
+            .child(Self::field("stackTrace", class_id::STACK_TRACE, true))
+            .child(Self::field("state", class_id::THREAD_STATE, true))
+    }
+
+    fn thread_lifetime_event(id: i64, name: &str, label: &str) -> Self {
+        Self::event(id, name, label, &["Java Application"])
+            .child(Self::field("eventThread", class_id::THREAD, true))
+            .child(Self::field("thread", class_id::THREAD, true))
+    }
+
+    fn collect_strings(&self, strings: &mut Pool<String>) {
+        strings.intern(self.name.to_string());
+        for (k, v) in self.attributes.iter() {
+            strings.intern(k.clone());
+            strings.intern(v.clone());
+        }
+        for child in self.children.iter() {
+            child.collect_strings(strings);
+        }
+    }
+
+    /// Pool indices are 1-origin while the string table is 0-origin
+    fn write(&self, w: &mut ByteWriter, strings: &Pool<String>, write_name: bool) {
+        let index = |s: &str| strings.indices[s] as i32 - 1;
+        if write_name {
+            w.write_int(index(self.name));
+        }
+        w.write_int(self.attributes.len() as i32);
+        for (k, v) in self.attributes.iter() {
+            w.write_int(index(k.as_str()));
+            w.write_int(index(v.as_str()));
+        }
+        w.write_int(self.children.len() as i32);
+        for child in self.children.iter() {
+            child.write(w, strings, true);
+        }
+    }
+}
+
+fn write_metadata(w: &mut ByteWriter) -> Result<()> {
+    let annotation = |id, name, array| {
+        let value = if array {
+            Element::array_field("value", class_id::STRING)
+        } else {
+            Element::field("value", class_id::STRING, false)
+        };
+        Element::class(id, name)
+            .attribute("superType", "java.lang.annotation.Annotation")
+            .child(value)
+    };
+
+    let metadata =
+        Element::new("metadata")
+            .child(Element::class(class_id::BOOLEAN, "boolean"))
+            .child(Element::class(class_id::INT, "int"))
+            .child(Element::class(class_id::LONG, "long"))
+            .child(Element::class(class_id::STRING, "java.lang.String"))
+            .child(annotation(
+                class_id::ANNOTATION_LABEL,
+                "jdk.jfr.Label",
+                false,
+            ))
+            .child(annotation(
+                class_id::ANNOTATION_CATEGORY,
+                "jdk.jfr.Category",
+                true,
+            ))
+            .child(annotation(
+                class_id::ANNOTATION_TIMESTAMP,
+                "jdk.jfr.Timestamp",
+                false,
+            ))
+            .child(
+                Element::class(class_id::THREAD, "java.lang.Thread")
+                    .child(Element::field("osName", class_id::STRING, false))
+                    .child(Element::field("osThreadId", class_id::LONG, false))
+                    .child(Element::field("javaName", class_id::STRING, false))
+                    .child(Element::field("javaThreadId", class_id::LONG, false)),
+            )
+            .child(
+                Element::class(class_id::CLASS, "java.lang.Class")
+                    .child(Element::field("name", class_id::SYMBOL, true))
+                    .child(Element::field("modifiers", class_id::INT, false)),
+            )
+            .child(
+                Element::class(class_id::FRAME_TYPE, "jdk.types.FrameType").child(Element::field(
+                    "description",
+                    class_id::STRING,
+                    false,
+                )),
+            )
+            .child(
+                Element::class(class_id::THREAD_STATE, "jdk.types.ThreadState")
+                    .child(Element::field("name", class_id::STRING, false)),
+            )
+            .child(
+                Element::class(class_id::STACK_TRACE, "jdk.types.StackTrace")
+                    .child(Element::field("truncated", class_id::BOOLEAN, false))
+                    .child(Element::array_field("frames", class_id::STACK_FRAME)),
+            )
+            .child(
+                Element::class(class_id::STACK_FRAME, "jdk.types.StackFrame")
+                    .child(Element::field("method", class_id::METHOD, true))
+                    .child(Element::field("lineNumber", class_id::INT, false))
+                    .child(Element::field("bytecodeIndex", class_id::INT, false))
+                    .child(Element::field("type", class_id::FRAME_TYPE, true)),
+            )
+            .child(
+                Element::class(class_id::METHOD, "jdk.types.Method")
+                    .child(Element::field("type", class_id::CLASS, true))
+                    .child(Element::field("name", class_id::SYMBOL, true))
+                    .child(Element::field("descriptor", class_id::SYMBOL, true))
+                    .child(Element::field("modifiers", class_id::INT, false))
+                    .child(Element::field("hidden", class_id::BOOLEAN, false)),
+            )
+            .child(
+                Element::class(class_id::SYMBOL, "jdk.types.Symbol").child(Element::field(
+                    "string",
+                    class_id::STRING,
+                    false,
+                )),
+            )
+            .child(Element::sample_event(
+                class_id::EXECUTION_SAMPLE,
+                "jdk.ExecutionSample",
+                "Method Profiling Sample",
+            ))
+            .child(Element::sample_event(
+                class_id::NATIVE_METHOD_SAMPLE,
+                "jdk.NativeMethodSample",
+                "Method Profiling Sample Native",
+            ))
+            .child(Element::thread_lifetime_event(
+                class_id::THREAD_START,
+                "jdk.ThreadStart",
+                "Java Thread Start",
+            ))
+            .child(Element::thread_lifetime_event(
+                class_id::THREAD_END,
+                "jdk.ThreadEnd",
+                "Java Thread End",
+            ));
+    let region = Element::new("region")
+        .attribute("locale", "en_US")
+        .attribute("gmtOffset", 0);
+    let root = Element::new("root").child(metadata).child(region);
+
+    let mut strings = Pool::default();
+    root.collect_strings(&mut strings);
+
+    let start = w.begin_event(EVENT_TYPE_METADATA);
+    // start time, duration and metadata id
+    w.write_long(0);
+    w.write_long(0);
+    w.write_long(0);
+    w.write_int(strings.values.len() as i32);
+    for s in strings.values.iter() {
+        w.write_string(Some(s.as_str()));
+    }
+    // root element name is written unlike other elements' name
+    w.write_int(strings.indices["root"] as i32 - 1);
+    root.write(w, &strings, false);
+    w.end_event(start)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::execution_sample::{Filter, Profile};
+    use crate::flame_graph::FlameGraph;
+    use crate::jfr::write;
+    use crate::profile::Thread;
+    use crate::test_util::load;
+    use jfrs::reader::event::Accessor;
+    use jfrs::reader::JfrReader;
+    use std::io::Cursor;
+
+    fn sorted_folded(profile: &Profile) -> Vec<String> {
+        let mut lines: Vec<_> = FlameGraph::from_execution_sample(profile, &Default::default())
+            .to_folded()
+            .lines()
+            .map(|l| l.to_string())
+            .collect();
+        lines.sort();
+        lines
+    }
+
+    #[test]
+    fn test_write() {
+        for name in ["profiler-wall.jfr", "profiler-multichunk.jfr"] {
+            let original = load(name);
+
+            let mut reloaded = Profile::default();
+            reloaded.load(write(&original).unwrap()).unwrap();
+
+            assert_eq!(
+                reloaded
+                    .filtered_threads()
+                    .iter()
+                    .map(|t| &t.name)
+                    .collect::<Vec<_>>(),
+                original
+                    .filtered_threads()
+                    .iter()
+                    .map(|t| &t.name)
+                    .collect::<Vec<_>>()
+            );
+            for thread in original.filtered_threads() {
+                let timestamps = |p: &Profile| {
+                    p.per_thread_samples[&thread.id]
+                        .iter()
+                        .map(|s| (s.timestamp_nanos, s.state.name()))
+                        .collect::<Vec<_>>()
+                };
+                assert_eq!(timestamps(&reloaded), timestamps(&original));
+            }
+            assert_eq!(reloaded.interval.start_nanos, original.interval.start_nanos);
+            assert_eq!(sorted_folded(&reloaded), sorted_folded(&original));
+        }
+    }
+
+    #[test]
+    fn test_write_filtered() {
+        let mut original = load("profiler-wall.jfr");
+        original
+            .apply_filter(Filter {
+                thread_name_regex: Some("GC Thread".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let mut reloaded = Profile::default();
+        reloaded.load(write(&original).unwrap()).unwrap();
+
+        assert_eq!(reloaded.filtered_threads().len(), 4);
+        assert!(reloaded
+            .filtered_threads()
+            .iter()
+            .all(|t| t.name.starts_with("GC Thread")));
+        assert_eq!(sorted_folded(&reloaded), sorted_folded(&original));
+    }
+
+    #[test]
+    fn test_write_time_range() {
+        let mut original = load("profiler-wall.jfr");
+        let start_nanos = original.interval.timestamp_at(0.25);
+        let end_nanos = original.interval.timestamp_at(0.5);
+        original
+            .apply_filter(Filter {
+                start_nanos: Some(start_nanos),
+                end_nanos: Some(end_nanos),
+                ..Default::default()
+            })
+            .unwrap();
+        let timestamps = original
+            .per_thread_samples
+            .values()
+            .flatten()
+            .map(|s| s.timestamp_nanos)
+            .filter(|&t| start_nanos <= t && t <= end_nanos)
+            .collect::<Vec<_>>();
+
+        // the chunk covers only the written samples
+        let bytes = write(&original).unwrap();
+        let mut reader = JfrReader::new(Cursor::new(bytes));
+        let (_, chunk) = reader.chunks().next().unwrap().unwrap();
+        let first = *timestamps.iter().min().unwrap();
+        let last = *timestamps.iter().max().unwrap();
+        assert_eq!(chunk.header.start_time_nanos, first);
+        assert_eq!(chunk.header.duration_nanos, last - first);
+    }
+
+    #[test]
+    fn test_write_thread_lifetime() {
+        let original = load("hotspot-threads.jfr");
+        let bytes = write(&original).unwrap();
+
+        let mut reader = JfrReader::new(Cursor::new(bytes));
+        let (mut reader, chunk) = reader.chunks().next().unwrap().unwrap();
+        let type_names = chunk
+            .metadata
+            .type_pool
+            .get_types()
+            .map(|t| t.name())
+            .collect::<Vec<_>>();
+        for name in [
+            "jdk.ExecutionSample",
+            "jdk.NativeMethodSample",
+            "jdk.ThreadStart",
+            "jdk.ThreadEnd",
+        ] {
+            assert!(type_names.contains(&name), "{} is not declared", name);
+        }
+        let thread_type = chunk
+            .metadata
+            .type_pool
+            .get_types()
+            .find(|t| t.name() == "java.lang.Thread")
+            .unwrap();
+        assert_eq!(
+            thread_type
+                .fields
+                .iter()
+                .map(|f| f.name())
+                .collect::<Vec<_>>(),
+            vec!["osName", "osThreadId", "javaName", "javaThreadId"]
+        );
+
+        // each thread starts at the first sample and ends at the last one
+        fn field<'a>(accessor: &Accessor<'a>, name: &str) -> Accessor<'a> {
+            accessor.get_field(name).unwrap()
+        }
+        let mut lifetimes = vec![];
+        for event in reader.events(&chunk).flatten() {
+            let name = event.class.name();
+            if name != "jdk.ThreadStart" && name != "jdk.ThreadEnd" {
+                continue;
+            }
+            let thread = event.value().get_field("thread").unwrap();
+            let timestamp = i64::try_from(field(&event.value(), "startTime").value).unwrap()
+                - chunk.header.start_ticks
+                + chunk.header.start_time_nanos;
+            lifetimes.push((
+                name.to_string(),
+                <&str>::try_from(field(&thread, "javaName").value)
+                    .unwrap()
+                    .to_string(),
+                i64::try_from(field(&thread, "osThreadId").value).unwrap(),
+                i64::try_from(field(&thread, "javaThreadId").value).unwrap(),
+                timestamp,
+            ));
+        }
+        let expected = |thread: &Thread| {
+            let samples = &original.per_thread_samples[&thread.id];
+            [
+                ("jdk.ThreadStart", samples[0].timestamp_nanos),
+                ("jdk.ThreadEnd", samples[samples.len() - 1].timestamp_nanos),
+            ]
+            .map(|(name, timestamp)| {
+                (
+                    name.to_string(),
+                    thread.name.clone(),
+                    thread.os_thread_id,
+                    thread.java_thread_id,
+                    timestamp,
+                )
+            })
+        };
+        assert_eq!(
+            lifetimes,
+            original
+                .filtered_threads()
+                .iter()
+                .flat_map(expected)
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -1,7 +1,18 @@
+pub mod demangle;
+pub mod encoding;
 pub mod execution_sample;
 pub mod flame_graph;
 pub mod jbm;
+pub mod jfr;
+pub mod pprof;
 pub mod profile;
+pub mod snapshot;
+pub mod speedscope;
+pub mod time;
+pub mod trace_event;
+
+#[cfg(test)]
+mod test_util;
 
 #[cfg(target_arch = "wasm32")]
 mod web;
@@ -9,6 +20,7 @@ mod web;
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
+#[cfg(target_arch = "wasm32")]
 use log::info;
 
 use serde::{Deserialize, Serialize};
@@ -42,19 +54,29 @@ pub struct Dimension {
 #[cfg_attr(target_arch = "wasm32", tsify(into_wasm_abi, from_wasm_abi))]
 #[serde(rename_all = "camelCase")]
 pub struct TimeInterval {
-    pub start_millis: i64,
-    pub end_millis: i64,
+    pub start_nanos: i64,
+    pub end_nanos: i64,
 }
 
 impl TimeInterval {
-    pub fn new(start_millis: i64, end_millis: i64) -> Self {
+    pub fn new(start_nanos: i64, end_nanos: i64) -> Self {
         Self {
-            start_millis,
-            end_millis,
+            start_nanos,
+            end_nanos,
         }
     }
 
-    pub fn duration_millis(&self) -> i64 {
-        self.end_millis - self.start_millis
+    pub fn duration_nanos(&self) -> i64 {
+        self.end_nanos - self.start_nanos
+    }
+
+    /// The position of the timestamp in the interval, where 0.0 is the start and 1.0 is the end
+    pub fn elapsed_ratio(&self, timestamp_nanos: i64) -> f64 {
+        (timestamp_nanos - self.start_nanos) as f64 / self.duration_nanos().max(1) as f64
+    }
+
+    /// The timestamp at the position in the interval, i.e. the inverse of elapsed_ratio
+    pub fn timestamp_at(&self, ratio: f64) -> i64 {
+        self.start_nanos + (self.duration_nanos() as f64 * ratio).round() as i64
     }
 }
@@ -0,0 +1,595 @@
+//! Conversion from/to pprof's profile.proto format.
+//! Should not contain any wasm dependencies.
+
+use crate::encoding::{self, Encoding};
+use crate::execution_sample::Profile;
+use crate::flame_graph::folded::{infer_frame_type, stack_frame};
+use crate::flame_graph::{AggregationConfig, Aggregator, FlameGraph, Granularity};
+use crate::profile::{FrameType, StackFrame, StackTrace};
+use anyhow::{anyhow, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use prost::Message;
+use rustc_hash::FxHashMap;
+use std::io::Write;
+
+/// Mapping name used to mark kernel frames, following perf's convention
+const KERNEL_MAPPING: &str = "[kernel.kallsyms]";
+const LABEL_THREAD: &str = "thread";
+const LABEL_STATE: &str = "state";
+
+/// Export the execution samples passing the current filter as gzipped profile.proto.
+/// Thread name and thread state are attached to each sample as labels.
+/// Functions are named and merged in the same way as the flame graph with the config,
+/// while line numbers are kept in the locations unless the granularity is coarser than lines.
+pub fn export(profile: &Profile, config: &AggregationConfig) -> Result<Vec<u8>> {
+    let mut builder = Builder {
+        aggregator: Aggregator::new(config),
+        keeps_line_number: config.granularity == Granularity::Line,
+        ..Default::default()
+    };
+    // index 0 of the string table must be an empty string
+    builder.string("");
+    builder.proto.sample_type = vec![proto::ValueType {
+        r#type: builder.string("samples"),
+        unit: builder.string("count"),
+    }];
+    builder.proto.period_type = Some(builder.proto.sample_type[0].clone());
+    builder.proto.period = 1;
+    if profile.interval.start_nanos <= profile.interval.end_nanos {
+        builder.proto.time_nanos = profile.interval.start_nanos;
+        builder.proto.duration_nanos = profile.interval.duration_nanos();
+    }
+
+    for thread in profile.filtered_threads() {
+        let mut pre_aggregation = FxHashMap::default();
+        if let Some(samples) = profile.per_thread_samples.get(&thread.id) {
+            for sample in samples {
+                if profile.is_valid_sample(thread, sample) {
+                    *pre_aggregation
+                        .entry((sample.stack_trace_key, sample.state.name()))
+                        .or_insert(0i64) += 1;
+                }
+            }
+        }
+
+        let mut entries: Vec<_> = pre_aggregation.into_iter().collect();
+        entries.sort_by_key(|((k, state), _)| (k.chunk_seq, k.constant_pool_index, *state));
+        for ((stack_trace_key, state), count) in entries {
+            if let Some(trace) = profile.stack_trace_pool.get(&stack_trace_key) {
+                // locations are ordered from the leaf
+                let frames = builder.aggregator.aggregate(trace);
+                let location_id = frames
+                    .into_iter()
+                    .rev()
+                    .map(|(f, _)| builder.location(f))
+                    .collect();
+                let label = vec![
+                    proto::Label {
+                        key: builder.string(LABEL_THREAD),
+                        str: builder.string(thread.name.as_str()),
+                        ..Default::default()
+                    },
+                    proto::Label {
+                        key: builder.string(LABEL_STATE),
+                        str: builder.string(state),
+                        ..Default::default()
+                    },
+                ];
+                builder.proto.sample.push(proto::Sample {
+                    location_id,
+                    value: vec![count],
+                    label,
+                });
+            }
+        }
+    }
+
+    let mut encoder = GzEncoder::new(vec![], Compression::default());
+    encoder.write_all(builder.proto.encode_to_vec().as_slice())?;
+    Ok(encoder.finish()?)
+}
+
+/// Import a (possibly gzipped) profile.proto into the flame graph.
+/// The default sample type is used as the weight, or the last one if not specified.
+///
+/// Frame types are inferred from function names in the same way as folded stacks,
+/// except kernel frames which are recognized by the mapping.
+pub fn import(bytes: Vec<u8>) -> Result<FlameGraph> {
+    let bytes = encoding::decode(bytes, Encoding::Auto, None)?;
+    let profile = proto::Profile::decode(bytes.as_slice())?;
+
+    let string = |i: i64| -> Result<&str> {
+        profile
+            .string_table
+            .get(i as usize)
+            .map(|s| s.as_str())
+            .ok_or_else(|| anyhow!("Failed to get string {}", i))
+    };
+    let functions: FxHashMap<_, _> = profile.function.iter().map(|f| (f.id, f)).collect();
+    let locations: FxHashMap<_, _> = profile.location.iter().map(|l| (l.id, l)).collect();
+    let mapping_names: FxHashMap<_, _> = profile
+        .mapping
+        .iter()
+        .map(|m| (m.id, string(m.filename).unwrap_or("")))
+        .collect();
+
+    let value_index = if profile.default_sample_type != 0 {
+        profile
+            .sample_type
+            .iter()
+            .position(|t| t.r#type == profile.default_sample_type)
+            .ok_or_else(|| anyhow!("Failed to get default sample type"))?
+    } else {
+        profile
+            .sample_type
+            .len()
+            .checked_sub(1)
+            .ok_or_else(|| anyhow!("Failed to get sample type"))?
+    };
+
+    let mut flame = FlameGraph::new();
+    for sample in profile.sample.iter() {
+        let count = sample.value.get(value_index).copied().unwrap_or(0).max(0) as u64;
+
+        let mut frames = vec![];
+        for location_id in sample.location_id.iter() {
+            let location = locations
+                .get(location_id)
+                .ok_or_else(|| anyhow!("Failed to get location {}", location_id))?;
+            let kernel = mapping_names.get(&location.mapping_id).copied() == Some(KERNEL_MAPPING);
+            if location.line.is_empty() {
+                let name = format!("0x{:x}", location.address);
+                let frame_type = if kernel {
+                    FrameType::Kernel
+                } else {
+                    FrameType::Native
+                };
+                frames.push(stack_frame(name.as_str(), frame_type, 0));
+            }
+            // lines except the last one are inlined into the last one
+            for (i, line) in location.line.iter().enumerate() {
+                let function = functions
+                    .get(&line.function_id)
+                    .ok_or_else(|| anyhow!("Failed to get function {}", line.function_id))?;
+                let name = string(function.name)?;
+                let frame_type = match infer_frame_type(name) {
+                    _ if kernel => FrameType::Kernel,
+                    FrameType::JitCompiled if i + 1 < location.line.len() => FrameType::Inlined,
+                    t => t,
+                };
+                frames.push(stack_frame(name, frame_type, line.line as i32));
+            }
+        }
+        flame.add_sample(
+            &StackTrace {
+                frames,
+                truncated: false,
+            },
+            count,
+        );
+    }
+
+    Ok(flame)
+}
+
+#[derive(Default)]
+struct Builder {
+    proto: proto::Profile,
+    strings: FxHashMap<String, i64>,
+    functions: FxHashMap<String, u64>,
+    mappings: FxHashMap<String, u64>,
+    /// Keyed by the function name, the line number and the mapping id
+    locations: FxHashMap<(String, i64, u64), u64>,
+    aggregator: Aggregator,
+    keeps_line_number: bool,
+}
+
+impl Builder {
+    fn string(&mut self, s: &str) -> i64 {
+        if let Some(&i) = self.strings.get(s) {
+            return i;
+        }
+        let i = self.proto.string_table.len() as i64;
+        self.proto.string_table.push(s.to_string());
+        self.strings.insert(s.to_string(), i);
+        i
+    }
+
+    fn function(&mut self, name: String) -> u64 {
+        if let Some(&id) = self.functions.get(&name) {
+            return id;
+        }
+        let id = self.proto.function.len() as u64 + 1;
+        let name_index = self.string(name.as_str());
+        self.proto.function.push(proto::Function {
+            id,
+            name: name_index,
+            system_name: name_index,
+            ..Default::default()
+        });
+        self.functions.insert(name, id);
+        id
+    }
+
+    fn mapping(&mut self, filename: &str) -> u64 {
+        if let Some(&id) = self.mappings.get(filename) {
+            return id;
+        }
+        let id = self.proto.mapping.len() as u64 + 1;
+        let filename_index = self.string(filename);
+        self.proto.mapping.push(proto::Mapping {
+            id,
+            filename: filename_index,
+            has_functions: true,
+            ..Default::default()
+        });
+        self.mappings.insert(filename.to_string(), id);
+        id
+    }
+
+    fn location(&mut self, frame: &StackFrame) -> u64 {
+        // the line number goes to the location rather than the function name
+        let function_name = self
+            .aggregator
+            .frame_id(
+                &StackFrame::new(
+                    frame.type_name.clone(),
+                    frame.method_name.clone(),
+                    frame.frame_type,
+                    0,
+                )
+                .with_method_signature(frame.descriptor.clone(), frame.modifiers),
+            )
+            .name;
+        let line = if self.keeps_line_number {
+            frame.line_number.max(0) as i64
+        } else {
+            0
+        };
+        let mapping_id = match frame.frame_type {
+            FrameType::Kernel => self.mapping(KERNEL_MAPPING),
+            FrameType::Native | FrameType::Cpp if !frame.type_name.is_empty() => {
+                self.mapping(frame.type_name.as_str())
+            }
+            _ => 0,
+        };
+
+        let key = (function_name, line, mapping_id);
+        if let Some(&id) = self.locations.get(&key) {
+            return id;
+        }
+        let id = self.proto.location.len() as u64 + 1;
+        let function_id = self.function(key.0.clone());
+        self.proto.location.push(proto::Location {
+            id,
+            mapping_id,
+            line: vec![proto::Line { function_id, line }],
+            ..Default::default()
+        });
+        self.locations.insert(key, id);
+        id
+    }
+}
+
+/// Messages of profile.proto.
+/// See https://github.com/google/pprof/blob/main/proto/profile.proto
+mod proto {
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Profile {
+        #[prost(message, repeated, tag = "1")]
+        pub sample_type: Vec<ValueType>,
+        #[prost(message, repeated, tag = "2")]
+        pub sample: Vec<Sample>,
+        #[prost(message, repeated, tag = "3")]
+        pub mapping: Vec<Mapping>,
+        #[prost(message, repeated, tag = "4")]
+        pub location: Vec<Location>,
+        #[prost(message, repeated, tag = "5")]
+        pub function: Vec<Function>,
+        #[prost(string, repeated, tag = "6")]
+        pub string_table: Vec<String>,
+        #[prost(int64, tag = "7")]
+        pub drop_frames: i64,
+        #[prost(int64, tag = "8")]
+        pub keep_frames: i64,
+        #[prost(int64, tag = "9")]
+        pub time_nanos: i64,
+        #[prost(int64, tag = "10")]
+        pub duration_nanos: i64,
+        #[prost(message, optional, tag = "11")]
+        pub period_type: Option<ValueType>,
+        #[prost(int64, tag = "12")]
+        pub period: i64,
+        #[prost(int64, repeated, tag = "13")]
+        pub comment: Vec<i64>,
+        #[prost(int64, tag = "14")]
+        pub default_sample_type: i64,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct ValueType {
+        #[prost(int64, tag = "1")]
+        pub r#type: i64,
+        #[prost(int64, tag = "2")]
+        pub unit: i64,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Sample {
+        #[prost(uint64, repeated, tag = "1")]
+        pub location_id: Vec<u64>,
+        #[prost(int64, repeated, tag = "2")]
+        pub value: Vec<i64>,
+        #[prost(message, repeated, tag = "3")]
+        pub label: Vec<Label>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Label {
+        #[prost(int64, tag = "1")]
+        pub key: i64,
+        #[prost(int64, tag = "2")]
+        pub str: i64,
+        #[prost(int64, tag = "3")]
+        pub num: i64,
+        #[prost(int64, tag = "4")]
+        pub num_unit: i64,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Mapping {
+        #[prost(uint64, tag = "1")]
+        pub id: u64,
+        #[prost(uint64, tag = "2")]
+        pub memory_start: u64,
+        #[prost(uint64, tag = "3")]
+        pub memory_limit: u64,
+        #[prost(uint64, tag = "4")]
+        pub file_offset: u64,
+        #[prost(int64, tag = "5")]
+        pub filename: i64,
+        #[prost(int64, tag = "6")]
+        pub build_id: i64,
+        #[prost(bool, tag = "7")]
+        pub has_functions: bool,
+        #[prost(bool, tag = "8")]
+        pub has_filenames: bool,
+        #[prost(bool, tag = "9")]
+        pub has_line_numbers: bool,
+        #[prost(bool, tag = "10")]
+        pub has_inline_frames: bool,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Location {
+        #[prost(uint64, tag = "1")]
+        pub id: u64,
+        #[prost(uint64, tag = "2")]
+        pub mapping_id: u64,
+        #[prost(uint64, tag = "3")]
+        pub address: u64,
+        #[prost(message, repeated, tag = "4")]
+        pub line: Vec<Line>,
+        #[prost(bool, tag = "5")]
+        pub is_folded: bool,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Line {
+        #[prost(uint64, tag = "1")]
+        pub function_id: u64,
+        #[prost(int64, tag = "2")]
+        pub line: i64,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Function {
+        #[prost(uint64, tag = "1")]
+        pub id: u64,
+        #[prost(int64, tag = "2")]
+        pub name: i64,
+        #[prost(int64, tag = "3")]
+        pub system_name: i64,
+        #[prost(int64, tag = "4")]
+        pub filename: i64,
+        #[prost(int64, tag = "5")]
+        pub start_line: i64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::execution_sample::Filter;
+    use crate::flame_graph::{AggregationConfig, FlameGraph, Frame, Granularity};
+    use crate::pprof::{export, import, proto};
+    use crate::profile::FrameType;
+    use crate::test_util::load;
+    use flate2::read::GzDecoder;
+    use prost::Message;
+    use std::io::Read;
+
+    #[test]
+    fn test_export() {
+        let mut profile = load("profiler-wall.jfr");
+        profile
+            .apply_filter(Filter {
+                thread_name_regex: Some("GC Thread#0".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let mut bytes = vec![];
+        GzDecoder::new(export(&profile, &Default::default()).unwrap().as_slice())
+            .read_to_end(&mut bytes)
+            .unwrap();
+        let proto = proto::Profile::decode(bytes.as_slice()).unwrap();
+        assert_eq!(proto.string_table[0], "");
+
+        let flame = FlameGraph::from_execution_sample(&profile, &Default::default());
+        let total: i64 = proto.sample.iter().map(|s| s.value[0]).sum();
+        assert_eq!(total as u64, flame.root.total_count);
+
+        for sample in proto.sample.iter() {
+            let labels: Vec<_> = sample
+                .label
+                .iter()
+                .map(|l| {
+                    (
+                        proto.string_table[l.key as usize].as_str(),
+                        proto.string_table[l.str as usize].as_str(),
+                    )
+                })
+                .collect();
+            assert_eq!(labels[0], ("thread", "GC Thread#0"));
+            assert_eq!(labels[1].0, "state");
+        }
+    }
+
+    #[test]
+    fn test_export_aggregation() {
+        let profile = load("profiler-wall.jfr");
+        let config = AggregationConfig {
+            signature: true,
+            ..Default::default()
+        };
+
+        let mut bytes = vec![];
+        GzDecoder::new(export(&profile, &config).unwrap().as_slice())
+            .read_to_end(&mut bytes)
+            .unwrap();
+        let proto = proto::Profile::decode(bytes.as_slice()).unwrap();
+        let names = proto
+            .function
+            .iter()
+            .map(|f| proto.string_table[f.name as usize].as_str())
+            .collect::<Vec<_>>();
+        assert!(names.iter().any(|n| n.contains('(')));
+        // line numbers are kept in the locations rather than the names
+        assert!(names
+            .iter()
+            .filter(|n| n.contains('('))
+            .all(|n| n.ends_with(')')));
+        assert!(proto.location.iter().any(|l| l.line[0].line > 0));
+    }
+
+    #[test]
+    fn test_export_granularity() {
+        let profile = load("profiler-wall.jfr");
+        let config = AggregationConfig {
+            granularity: Granularity::Package,
+            ..Default::default()
+        };
+
+        let mut bytes = vec![];
+        GzDecoder::new(export(&profile, &config).unwrap().as_slice())
+            .read_to_end(&mut bytes)
+            .unwrap();
+        let proto = proto::Profile::decode(bytes.as_slice()).unwrap();
+        let names = proto
+            .function
+            .iter()
+            .map(|f| proto.string_table[f.name as usize].as_str())
+            .collect::<Vec<_>>();
+        assert!(names.contains(&"java/lang"));
+        assert!(proto.location.iter().all(|l| l.line[0].line == 0));
+
+        // consecutive frames in a package are merged like the flame graph
+        let flame = FlameGraph::from_execution_sample(&profile, &config);
+        let mut max_depth = 0;
+        for sample in proto.sample.iter() {
+            assert!(sample.location_id.windows(2).all(|w| w[0] != w[1]));
+            max_depth = max_depth.max(sample.location_id.len());
+        }
+        assert_eq!(max_depth, flame.depth);
+    }
+
+    #[test]
+    fn test_import() {
+        let profile = load("profiler-wall.jfr");
+        let expected = FlameGraph::from_execution_sample(&profile, &Default::default());
+        let flame = import(export(&profile, &Default::default()).unwrap()).unwrap();
+
+        assert_eq!(flame.root.total_count, expected.root.total_count);
+        assert_eq!(flame.depth, expected.depth);
+        assert_eq!(
+            flame
+                .root
+                .children
+                .keys()
+                .map(|k| &k.name)
+                .collect::<Vec<_>>(),
+            expected
+                .root
+                .children
+                .keys()
+                .map(|k| &k.name)
+                .collect::<Vec<_>>()
+        );
+
+        assert!(count_frames(&flame.root, FrameType::Cpp) > 0);
+        assert!(count_frames(&flame.root, FrameType::Native) > 0);
+    }
+
+    #[test]
+    fn test_import_kernel_frame() {
+        let mut profile = proto::Profile {
+            string_table: [
+                "",
+                "samples",
+                "count",
+                "[kernel.kallsyms]",
+                "do_syscall_64",
+                "write",
+            ]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+            sample_type: vec![proto::ValueType { r#type: 1, unit: 2 }],
+            mapping: vec![proto::Mapping {
+                id: 1,
+                filename: 3,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        for (id, mapping_id) in [(1u64, 1u64), (2, 0)] {
+            profile.function.push(proto::Function {
+                id,
+                name: id as i64 + 3,
+                ..Default::default()
+            });
+            profile.location.push(proto::Location {
+                id,
+                mapping_id,
+                line: vec![proto::Line {
+                    function_id: id,
+                    line: 0,
+                }],
+                ..Default::default()
+            });
+        }
+        profile.sample.push(proto::Sample {
+            location_id: vec![1, 2],
+            value: vec![7],
+            label: vec![],
+        });
+
+        let flame = import(profile.encode_to_vec()).unwrap();
+        assert_eq!(flame.root.total_count, 7);
+        let (id, write) = flame.root.children.iter().next().unwrap();
+        assert_eq!(id.name, "write");
+        assert_eq!(write.calculated_type(), FrameType::Native);
+        let (id, syscall) = write.children.iter().next().unwrap();
+        assert_eq!(id.name, "do_syscall_64");
+        assert_eq!(syscall.calculated_type(), FrameType::Kernel);
+    }
+
+    fn count_frames(frame: &Frame, frame_type: FrameType) -> usize {
+        frame
+            .children
+            .values()
+            .map(|f| (f.calculated_type() == frame_type) as usize + count_frames(f, frame_type))
+            .sum()
+    }
+}
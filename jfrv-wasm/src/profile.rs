@@ -1,6 +1,7 @@
 //! Data structures to represent JFR profiles.
 //! Should not contain any wasm dependencies.
 
+use crate::demangle;
 use serde::{Deserialize, Serialize};
 #[cfg(target_arch = "wasm32")]
 use tsify::Tsify;
@@ -16,9 +17,27 @@ pub struct StackFrame {
     pub method_name: String,
     pub frame_type: FrameType,
     pub line_number: i32,
+    /// JVM method descriptor like `([BII)V`. Empty if unknown
+    pub descriptor: String,
+    /// Access flags of the method as defined in the class file format
+    pub modifiers: i32,
+    /// The raw symbol if method_name is demangled
+    pub mangled_name: Option<String>,
     name: String,
 }
 
+/// Method access flags and their keywords in the declaration order
+const METHOD_MODIFIERS: [(i32, &str); 8] = [
+    (0x0001, "public"),
+    (0x0002, "private"),
+    (0x0004, "protected"),
+    (0x0400, "abstract"),
+    (0x0008, "static"),
+    (0x0010, "final"),
+    (0x0020, "synchronized"),
+    (0x0100, "native"),
+];
+
 impl StackFrame {
     pub fn new(
         type_name: String,
@@ -48,13 +67,139 @@ impl StackFrame {
             method_name,
             frame_type,
             line_number,
+            descriptor: String::new(),
+            modifiers: 0,
+            mangled_name: None,
             name,
         }
     }
 
+    /// Demangle C++ or Rust symbols in the method name of native frames
+    pub fn demangled(self) -> Self {
+        if self.is_java_method() {
+            return self;
+        }
+        match demangle::demangle_symbols(self.method_name.as_str()) {
+            Some(demangled) => {
+                let mut frame =
+                    Self::new(self.type_name, demangled, self.frame_type, self.line_number)
+                        .with_method_signature(self.descriptor, self.modifiers);
+                frame.mangled_name = Some(self.method_name);
+                frame
+            }
+            None => self,
+        }
+    }
+
+    pub fn with_method_signature(mut self, descriptor: String, modifiers: i32) -> Self {
+        self.descriptor = descriptor;
+        self.modifiers = modifiers;
+        self
+    }
+
     pub fn name(&self) -> &str {
         self.name.as_str()
     }
+
+    fn is_java_method(&self) -> bool {
+        !self.type_name.is_empty()
+            && matches!(
+                self.frame_type,
+                FrameType::Interpreted
+                    | FrameType::JitCompiled
+                    | FrameType::Inlined
+                    | FrameType::C1Compiled
+            )
+    }
+
+    /// The name with parameter types, e.g. `java/io/OutputStream.write(byte[], int, int):42`,
+    /// which distinguishes overloaded methods.
+    /// Same as name() if the descriptor is unknown.
+    pub fn signature_name(&self) -> String {
+        match parse_descriptor(self.descriptor.as_str()) {
+            Some((params, _)) if self.is_java_method() => {
+                let line_num = if self.line_number > 0 {
+                    format!(":{}", self.line_number)
+                } else {
+                    "".to_string()
+                };
+                format!(
+                    "{}.{}({}){}",
+                    self.type_name,
+                    self.method_name,
+                    params.join(", "),
+                    line_num
+                )
+            }
+            _ => self.name.clone(),
+        }
+    }
+
+    /// Human-readable declaration of the method, e.g.
+    /// `public void java/io/OutputStream.write(byte[], int, int):42`.
+    /// Same as name() if the descriptor is unknown.
+    pub fn signature(&self) -> String {
+        match parse_descriptor(self.descriptor.as_str()) {
+            Some((_, return_type)) if self.is_java_method() => {
+                let mut result = String::new();
+                for (flag, keyword) in METHOD_MODIFIERS {
+                    if self.modifiers & flag != 0 {
+                        result.push_str(keyword);
+                        result.push(' ');
+                    }
+                }
+                result.push_str(return_type.as_str());
+                result.push(' ');
+                result.push_str(self.signature_name().as_str());
+                result
+            }
+            _ => self.name.clone(),
+        }
+    }
+}
+
+/// Parse the method descriptor into human-readable parameter types and the return type
+fn parse_descriptor(descriptor: &str) -> Option<(Vec<String>, String)> {
+    let (params, return_type) = descriptor.strip_prefix('(')?.split_once(')')?;
+
+    let mut types = vec![];
+    let mut rest = params;
+    while !rest.is_empty() {
+        let (t, r) = parse_field_type(rest)?;
+        types.push(t);
+        rest = r;
+    }
+
+    let return_type = match parse_field_type(return_type) {
+        Some((t, "")) => t,
+        _ if return_type == "V" => "void".to_string(),
+        _ => return None,
+    };
+    Some((types, return_type))
+}
+
+/// Parse the leading field type of the descriptor and return the rest
+fn parse_field_type(descriptor: &str) -> Option<(String, &str)> {
+    let primitive = match descriptor.chars().next()? {
+        'B' => "byte",
+        'C' => "char",
+        'D' => "double",
+        'F' => "float",
+        'I' => "int",
+        'J' => "long",
+        'S' => "short",
+        'Z' => "boolean",
+        'L' => {
+            let (class_name, rest) = descriptor[1..].split_once(';')?;
+            return Some((class_name.replace('/', "."), rest));
+        }
+        '[' => {
+            let (component, rest) = parse_field_type(&descriptor[1..])?;
+            return Some((format!("{}[]", component), rest));
+        }
+        _ => return None,
+    };
+    Some((primitive.to_string(), &descriptor[1..]))
 }
 
 #[derive(Clone, Default, Deserialize, Serialize, Eq, PartialEq, Hash)]
@@ -62,7 +207,12 @@ impl StackFrame {
 #[cfg_attr(target_arch = "wasm32", tsify(into_wasm_abi, from_wasm_abi))]
 #[serde(rename_all = "camelCase")]
 pub struct Thread {
+    /// Unique id of the thread in the profile, which is the key of per-thread samples.
+    /// os_thread_id can't be used for that because it may be reused by later threads
+    pub id: i64,
     pub os_thread_id: i64,
+    /// 0 if the thread isn't a Java thread or the id is unknown
+    pub java_thread_id: i64,
     pub name: String,
 }
 
@@ -72,25 +222,94 @@ pub struct Thread {
 #[serde(rename_all = "camelCase")]
 pub struct StackTrace {
     pub frames: Vec<StackFrame>,
+    /// Whether the frames near the root were dropped due to the depth limit
+    pub truncated: bool,
 }
 
-/// Compact representation of thread state (which is originally String)
+/// Compact representation of thread state (which is originally String).
+/// Covers the states of jdk.types.ThreadState written by HotSpot and async-profiler,
+/// and the java.lang.Thread.State names used by JVMTI based tools.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq, Hash)]
+#[cfg_attr(target_arch = "wasm32", derive(Tsify))]
+#[cfg_attr(target_arch = "wasm32", tsify(into_wasm_abi, from_wasm_abi))]
 pub enum ThreadState {
+    #[default]
     Unknown,
+    New,
     Runnable,
     Sleeping,
+    /// Waiting to enter a monitor
+    Blocked,
+    /// Object.wait() without timeout
+    Waiting,
+    /// Object.wait() with timeout
+    TimedWaiting,
+    /// LockSupport.park() without timeout
+    Parked,
+    /// LockSupport.park() with timeout
+    TimedParked,
+    InNative,
+    Terminated,
 }
 
 impl ThreadState {
+    pub const ALL: [ThreadState; 11] = [
+        Self::Unknown,
+        Self::New,
+        Self::Runnable,
+        Self::Sleeping,
+        Self::Blocked,
+        Self::Waiting,
+        Self::TimedWaiting,
+        Self::Parked,
+        Self::TimedParked,
+        Self::InNative,
+        Self::Terminated,
+    ];
+
+    const THREAD_STATE_UNKNOWN: &'static str = "STATE_UNKNOWN";
+    const THREAD_STATE_NEW: &'static str = "STATE_NEW";
     const THREAD_STATE_RUNNING: &'static str = "STATE_RUNNABLE";
     const THREAD_STATE_SLEEPING: &'static str = "STATE_SLEEPING";
+    const THREAD_STATE_BLOCKED: &'static str = "STATE_BLOCKED_ON_MONITOR_ENTER";
+    const THREAD_STATE_WAITING: &'static str = "STATE_IN_OBJECT_WAIT";
+    const THREAD_STATE_TIMED_WAITING: &'static str = "STATE_IN_OBJECT_WAIT_TIMED";
+    const THREAD_STATE_PARKED: &'static str = "STATE_PARKED";
+    const THREAD_STATE_TIMED_PARKED: &'static str = "STATE_PARKED_TIMED";
+    const THREAD_STATE_IN_NATIVE: &'static str = "STATE_IN_NATIVE";
+    const THREAD_STATE_TERMINATED: &'static str = "STATE_TERMINATED";
+
+    /// The name as written by HotSpot
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Unknown => Self::THREAD_STATE_UNKNOWN,
+            Self::New => Self::THREAD_STATE_NEW,
+            Self::Runnable => Self::THREAD_STATE_RUNNING,
+            Self::Sleeping => Self::THREAD_STATE_SLEEPING,
+            Self::Blocked => Self::THREAD_STATE_BLOCKED,
+            Self::Waiting => Self::THREAD_STATE_WAITING,
+            Self::TimedWaiting => Self::THREAD_STATE_TIMED_WAITING,
+            Self::Parked => Self::THREAD_STATE_PARKED,
+            Self::TimedParked => Self::THREAD_STATE_TIMED_PARKED,
+            Self::InNative => Self::THREAD_STATE_IN_NATIVE,
+            Self::Terminated => Self::THREAD_STATE_TERMINATED,
+        }
+    }
 }
 
 impl From<&str> for ThreadState {
     fn from(s: &str) -> Self {
         match s {
+            Self::THREAD_STATE_NEW => Self::New,
             Self::THREAD_STATE_RUNNING => Self::Runnable,
             Self::THREAD_STATE_SLEEPING => Self::Sleeping,
+            Self::THREAD_STATE_BLOCKED | "STATE_BLOCKED" => Self::Blocked,
+            Self::THREAD_STATE_WAITING | "STATE_WAITING" => Self::Waiting,
+            Self::THREAD_STATE_TIMED_WAITING | "STATE_TIMED_WAITING" => Self::TimedWaiting,
+            Self::THREAD_STATE_PARKED => Self::Parked,
+            Self::THREAD_STATE_TIMED_PARKED => Self::TimedParked,
+            Self::THREAD_STATE_IN_NATIVE => Self::InNative,
+            Self::THREAD_STATE_TERMINATED => Self::Terminated,
             _ => Self::Unknown,
         }
     }
@@ -121,6 +340,21 @@ impl FrameType {
     const FRAME_CPP: &'static str = "C++";
     const FRAME_KERNEL: &'static str = "Kernel";
     const FRAME_C1_COMPILED: &'static str = "C1 compiled";
+    const FRAME_UNKNOWN: &'static str = "Unknown";
+
+    /// The description of the frame type used in JFR's constant pool
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::Interpreted => Self::FRAME_INTERPRETED,
+            Self::JitCompiled => Self::FRAME_JIT_COMPILED,
+            Self::Inlined => Self::FRAME_INLINED,
+            Self::Native => Self::FRAME_NATIVE,
+            Self::Cpp => Self::FRAME_CPP,
+            Self::Kernel => Self::FRAME_KERNEL,
+            Self::C1Compiled => Self::FRAME_C1_COMPILED,
+            Self::Unknown => Self::FRAME_UNKNOWN,
+        }
+    }
 }
 
 impl From<&str> for FrameType {
@@ -138,20 +372,121 @@ impl From<&str> for FrameType {
     }
 }
 
+/// The event type which an execution sample originates from
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq, Hash)]
+#[cfg_attr(target_arch = "wasm32", derive(Tsify))]
+#[cfg_attr(target_arch = "wasm32", tsify(into_wasm_abi, from_wasm_abi))]
+pub enum SampleEventType {
+    /// jdk.ExecutionSample, i.e. the thread was running Java code
+    #[default]
+    ExecutionSample,
+    /// jdk.NativeMethodSample, i.e. the thread was in native code
+    NativeMethodSample,
+}
+
+#[derive(Deserialize, Serialize)]
 pub struct ExecutionSample {
     pub timestamp_nanos: i64,
+    pub event_type: SampleEventType,
     pub state: ThreadState,
     pub stack_trace_key: ConstantPoolKey,
 }
 
+#[derive(Deserialize, Serialize)]
+pub struct AllocationSample {
+    pub timestamp_nanos: i64,
+    pub allocation_size: i64,
+    /// Whether the object was allocated outside of TLAB (i.e. directly in the heap)
+    pub outside_tlab: bool,
+    /// Class of the allocated object. May be absent if it couldn't be resolved
+    pub object_class_key: Option<ConstantPoolKey>,
+    pub stack_trace_key: ConstantPoolKey,
+}
+
+/// The kind of the event which a contention sample originates from
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Hash)]
+#[cfg_attr(target_arch = "wasm32", derive(Tsify))]
+#[cfg_attr(target_arch = "wasm32", tsify(into_wasm_abi, from_wasm_abi))]
+pub enum ContentionKind {
+    MonitorEnter,
+    ThreadPark,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ContentionSample {
+    pub timestamp_nanos: i64,
+    pub duration_nanos: i64,
+    pub kind: ContentionKind,
+    /// Class of the monitor (or the parked blocker object). May be absent for ThreadPark
+    pub monitor_class_key: Option<ConstantPoolKey>,
+    /// java thread id of the thread which held the monitor last. Only for MonitorEnter
+    pub previous_owner: Option<i64>,
+    pub stack_trace_key: ConstantPoolKey,
+}
+
+/// Common accessors of samples which refer to a stack trace in the pool
+pub trait Sample {
+    fn timestamp_nanos(&self) -> i64;
+    fn stack_trace_key(&self) -> ConstantPoolKey;
+
+    /// The event type of execution samples. None for other kinds of samples
+    fn event_type(&self) -> Option<SampleEventType> {
+        None
+    }
+
+    /// The thread state of execution samples. None for other kinds of samples
+    fn state(&self) -> Option<ThreadState> {
+        None
+    }
+}
+
+impl Sample for ExecutionSample {
+    fn timestamp_nanos(&self) -> i64 {
+        self.timestamp_nanos
+    }
+
+    fn stack_trace_key(&self) -> ConstantPoolKey {
+        self.stack_trace_key
+    }
+
+    fn event_type(&self) -> Option<SampleEventType> {
+        Some(self.event_type)
+    }
+
+    fn state(&self) -> Option<ThreadState> {
+        Some(self.state)
+    }
+}
+
+impl Sample for AllocationSample {
+    fn timestamp_nanos(&self) -> i64 {
+        self.timestamp_nanos
+    }
+
+    fn stack_trace_key(&self) -> ConstantPoolKey {
+        self.stack_trace_key
+    }
+}
+
+impl Sample for ContentionSample {
+    fn timestamp_nanos(&self) -> i64 {
+        self.timestamp_nanos
+    }
+
+    fn stack_trace_key(&self) -> ConstantPoolKey {
+        self.stack_trace_key
+    }
+}
+
+#[derive(Deserialize, Serialize)]
 pub struct OffCpu {
-    pub timestamp: i64,
-    pub duration_millis: i64,
+    pub timestamp_nanos: i64,
+    pub duration_nanos: i64,
     pub state: ThreadState,
     pub stack_trace_key: i32,
 }
 
-#[derive(Copy, Clone, Hash, Eq, PartialEq)]
+#[derive(Copy, Clone, Hash, Eq, PartialEq, Deserialize, Serialize)]
 pub struct ConstantPoolKey {
     pub chunk_seq: usize,
     pub class_id: i64,
@@ -167,3 +502,87 @@ impl ConstantPoolKey {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::profile::{FrameType, StackFrame, ThreadState};
+
+    fn frame(descriptor: &str, modifiers: i32) -> StackFrame {
+        StackFrame::new(
+            "java/io/OutputStream".to_string(),
+            "write".to_string(),
+            FrameType::JitCompiled,
+            42,
+        )
+        .with_method_signature(descriptor.to_string(), modifiers)
+    }
+
+    #[test]
+    fn test_signature() {
+        let frame1 = frame("([BII)V", 0x0001);
+        assert_eq!(
+            frame1.signature_name(),
+            "java/io/OutputStream.write(byte[], int, int):42"
+        );
+        assert_eq!(
+            frame1.signature(),
+            "public void java/io/OutputStream.write(byte[], int, int):42"
+        );
+
+        let frame2 = frame("(Ljava/nio/ByteBuffer;[[J)Ljava/lang/String;", 0x0009);
+        assert_eq!(
+            frame2.signature(),
+            "public static java.lang.String java/io/OutputStream.write(java.nio.ByteBuffer, long[][]):42"
+        );
+        assert_eq!(frame1.name(), frame2.name());
+
+        // fall back to the name if the descriptor is unknown or malformed
+        assert_eq!(frame("", 0).signature(), "java/io/OutputStream.write:42");
+        assert_eq!(
+            frame("(X)V", 0).signature_name(),
+            "java/io/OutputStream.write:42"
+        );
+        let native = StackFrame::new("".to_string(), "__write".to_string(), FrameType::Native, 0)
+            .with_method_signature("(I)V".to_string(), 0);
+        assert_eq!(native.signature(), "__write");
+    }
+
+    #[test]
+    fn test_demangled() {
+        let frame = StackFrame::new(
+            "".to_string(),
+            "_ZN2os13PlatformEvent4parkEl".to_string(),
+            FrameType::Cpp,
+            0,
+        )
+        .demangled();
+        assert_eq!(frame.name(), "os::PlatformEvent::park(long)");
+        assert_eq!(
+            frame.mangled_name.as_deref(),
+            Some("_ZN2os13PlatformEvent4parkEl")
+        );
+
+        let frame = StackFrame::new(
+            "".to_string(),
+            "pthread_cond_timedwait".to_string(),
+            FrameType::Native,
+            0,
+        )
+        .demangled();
+        assert_eq!(frame.name(), "pthread_cond_timedwait");
+        assert_eq!(frame.mangled_name, None);
+    }
+
+    #[test]
+    fn test_thread_state() {
+        for state in ThreadState::ALL {
+            assert_eq!(ThreadState::from(state.name()), state);
+        }
+        assert_eq!(ThreadState::from("STATE_BLOCKED"), ThreadState::Blocked);
+        assert_eq!(
+            ThreadState::from("STATE_TIMED_WAITING"),
+            ThreadState::TimedWaiting
+        );
+        assert_eq!(ThreadState::from("STATE_DEFAULT"), ThreadState::Unknown);
+    }
+}
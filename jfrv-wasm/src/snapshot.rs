@@ -0,0 +1,170 @@
+//! Binary serialization of loaded profiles, so that a session can be restored without re-parsing.
+//! A snapshot consists of a header (magic, format version and the kind of the profile)
+//! followed by the deflate-compressed bincode of the profile.
+//! Should not contain any wasm dependencies.
+
+use anyhow::{anyhow, Result};
+use bincode::Options;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io::Read;
+
+const MAGIC: &[u8] = b"JFRV";
+/// Should be bumped whenever the layout of the serialized profiles changes
+const VERSION: u8 = 13;
+const HEADER_SIZE: usize = MAGIC.len() + 2;
+
+/// The kind of the profile stored in a snapshot
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Kind {
+    ExecutionSample = 1,
+    Jbm = 2,
+}
+
+/// Serialize the profile into a snapshot
+pub fn save<T: Serialize>(kind: Kind, profile: &T) -> Result<Vec<u8>> {
+    let mut out = Vec::from(MAGIC);
+    out.push(VERSION);
+    out.push(kind as u8);
+
+    let mut encoder = DeflateEncoder::new(out, Compression::fast());
+    bincode::DefaultOptions::new().serialize_into(&mut encoder, profile)?;
+    Ok(encoder.finish()?)
+}
+
+/// Deserialize the profile from the snapshot
+pub fn restore<T: DeserializeOwned>(kind: Kind, bytes: &[u8]) -> Result<T> {
+    if bytes.len() < HEADER_SIZE || !bytes.starts_with(MAGIC) {
+        return Err(anyhow!("Not a jfrv snapshot"));
+    }
+    let version = bytes[MAGIC.len()];
+    if version != VERSION {
+        return Err(anyhow!(
+            "Unsupported snapshot version: {} (expected {})",
+            version,
+            VERSION
+        ));
+    }
+    let actual_kind = bytes[MAGIC.len() + 1];
+    if actual_kind != kind as u8 {
+        return Err(anyhow!(
+            "Snapshot is not of {:?} (kind: {})",
+            kind,
+            actual_kind
+        ));
+    }
+
+    let mut decompressed = vec![];
+    DeflateDecoder::new(&bytes[HEADER_SIZE..]).read_to_end(&mut decompressed)?;
+    // the profile never takes more bytes than the serialized one, so lengths beyond that
+    // are rejected before allocating for them
+    Ok(bincode::DefaultOptions::new()
+        .with_limit(decompressed.len() as u64)
+        .deserialize(&decompressed)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::execution_sample::{Filter, Profile};
+    use crate::flame_graph::FlameGraph;
+    use crate::jbm;
+    use crate::snapshot::{restore, Kind, HEADER_SIZE, MAGIC, VERSION};
+    use crate::test_util::{load, read};
+    use bincode::Options;
+    use flate2::write::DeflateEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    #[test]
+    fn test_execution_sample() {
+        let mut profile = load("profiler-multichunk.jfr");
+        profile
+            .apply_filter(Filter {
+                thread_name_regex: Some("GC Thread".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let restored = Profile::restore(&profile.snapshot().unwrap()).unwrap();
+        assert_eq!(
+            restored.filter().thread_name_regex.as_deref(),
+            Some("GC Thread")
+        );
+        assert_eq!(
+            restored
+                .filtered_threads()
+                .iter()
+                .map(|t| &t.name)
+                .collect::<Vec<_>>(),
+            profile
+                .filtered_threads()
+                .iter()
+                .map(|t| &t.name)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(restored.interval.start_nanos, profile.interval.start_nanos);
+        assert_eq!(restored.interval.end_nanos, profile.interval.end_nanos);
+        assert_eq!(restored.column_count, profile.column_count);
+        assert_eq!(
+            FlameGraph::from_execution_sample(&restored, &Default::default()).to_folded(),
+            FlameGraph::from_execution_sample(&profile, &Default::default()).to_folded()
+        );
+    }
+
+    #[test]
+    fn test_jbm() {
+        let mut profile = jbm::Profile::default();
+        profile.load(read("jbm.log"), &Default::default()).unwrap();
+
+        let restored = jbm::Profile::restore(&profile.snapshot().unwrap()).unwrap();
+        assert!(restored.filtered_threads() == profile.filtered_threads());
+        assert!(restored.stack_trace_pool == profile.stack_trace_pool);
+        assert_eq!(
+            restored
+                .per_thread_samples
+                .values()
+                .map(|s| s.len())
+                .sum::<usize>(),
+            profile
+                .per_thread_samples
+                .values()
+                .map(|s| s.len())
+                .sum::<usize>()
+        );
+    }
+
+    #[test]
+    fn test_restore_error() {
+        let mut profile = jbm::Profile::default();
+        profile.load(read("jbm.log"), &Default::default()).unwrap();
+        let snapshot = profile.snapshot().unwrap();
+
+        assert!(Profile::restore(&snapshot).is_err());
+        assert!(restore::<jbm::Profile>(Kind::Jbm, &read("jbm.log")).is_err());
+        assert!(jbm::Profile::restore(&snapshot[..snapshot.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn test_restore_huge_length() {
+        let mut snapshot = Vec::from(MAGIC);
+        snapshot.push(VERSION);
+        snapshot.push(Kind::Jbm as u8);
+        assert_eq!(snapshot.len(), HEADER_SIZE);
+
+        // a string which claims to be 1 TiB
+        let mut encoder = DeflateEncoder::new(snapshot, Compression::fast());
+        encoder
+            .write_all(
+                &bincode::DefaultOptions::new()
+                    .serialize(&(1u64 << 40))
+                    .unwrap(),
+            )
+            .unwrap();
+        let snapshot = encoder.finish().unwrap();
+
+        assert!(restore::<String>(Kind::Jbm, &snapshot).is_err());
+    }
+}
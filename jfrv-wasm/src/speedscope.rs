@@ -0,0 +1,249 @@
+//! Conversion to speedscope's file format.
+//! See https://github.com/jlfwong/speedscope/wiki/Importing-from-custom-sources
+//! Should not contain any wasm dependencies.
+
+use crate::execution_sample::Profile;
+use crate::flame_graph::{AggregationConfig, Aggregator};
+use crate::profile::ExecutionSample;
+use anyhow::Result;
+use rustc_hash::FxHashMap;
+use serde::Serialize;
+
+const SCHEMA: &str = "https://www.speedscope.app/file-format-schema.json";
+const EXPORTER: &str = "jfrv";
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct File {
+    #[serde(rename = "$schema")]
+    schema: &'static str,
+    shared: Shared,
+    profiles: Vec<SampledProfile>,
+    name: String,
+    exporter: &'static str,
+}
+
+#[derive(Default, Serialize)]
+struct Shared {
+    frames: Vec<Frame>,
+}
+
+#[derive(Serialize)]
+struct Frame {
+    name: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SampledProfile {
+    r#type: &'static str,
+    name: String,
+    unit: &'static str,
+    start_value: i64,
+    end_value: i64,
+    /// Stacks as indices of the shared frames, ordered from the root
+    samples: Vec<Vec<usize>>,
+    weights: Vec<i64>,
+}
+
+/// Export the execution samples passing the current filter as speedscope JSON.
+/// Each filtered thread becomes a "sampled" profile in time order, where each sample
+/// is weighted by the time until the next sample of the thread.
+/// Frames are named and merged in the same way as the flame graph with the config.
+pub fn export(profile: &Profile, name: &str, config: &AggregationConfig) -> Result<String> {
+    let aggregator = Aggregator::new(config);
+    let mut frames = Shared::default();
+    let mut frame_indices = FxHashMap::default();
+    let mut profiles = vec![];
+
+    for thread in profile.filtered_threads() {
+        let samples: Vec<&ExecutionSample> = profile
+            .per_thread_samples
+            .get(&thread.id)
+            .map(|samples| {
+                samples
+                    .iter()
+                    .filter(|s| profile.is_valid_sample(thread, *s))
+                    .filter(|s| profile.stack_trace_pool.contains_key(&s.stack_trace_key))
+                    .collect()
+            })
+            .unwrap_or_default();
+        if samples.is_empty() {
+            continue;
+        }
+
+        let mut result = SampledProfile {
+            r#type: "sampled",
+            name: thread.name.clone(),
+            unit: "nanoseconds",
+            start_value: samples[0].timestamp_nanos,
+            end_value: samples[0].timestamp_nanos,
+            samples: Vec::with_capacity(samples.len()),
+            weights: Vec::with_capacity(samples.len()),
+        };
+        for (i, sample) in samples.iter().enumerate() {
+            let trace = &profile.stack_trace_pool[&sample.stack_trace_key];
+            result.samples.push(
+                aggregator
+                    .aggregate(trace)
+                    .into_iter()
+                    .map(|(_, id)| frame_index(id.name, &mut frames, &mut frame_indices))
+                    .collect(),
+            );
+
+            // the last sample takes over the weight of the previous one
+            let weight = match (samples.get(i + 1), i.checked_sub(1)) {
+                (Some(next), _) => next.timestamp_nanos - sample.timestamp_nanos,
+                (None, Some(prev)) => result.weights[prev],
+                (None, None) => 0,
+            };
+            result.weights.push(weight);
+            result.end_value = sample.timestamp_nanos + weight;
+        }
+        profiles.push(result);
+    }
+
+    let file = File {
+        schema: SCHEMA,
+        shared: frames,
+        profiles,
+        name: name.to_string(),
+        exporter: EXPORTER,
+    };
+    Ok(serde_json::to_string(&file)?)
+}
+
+fn frame_index(
+    name: String,
+    frames: &mut Shared,
+    frame_indices: &mut FxHashMap<String, usize>,
+) -> usize {
+    if let Some(&i) = frame_indices.get(&name) {
+        return i;
+    }
+    let i = frames.frames.len();
+    frames.frames.push(Frame { name: name.clone() });
+    frame_indices.insert(name, i);
+    i
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::execution_sample::Filter;
+    use crate::flame_graph::{AggregationConfig, FlameGraph, Granularity};
+    use crate::speedscope::export;
+    use crate::test_util::load;
+    use serde_json::Value;
+
+    #[test]
+    fn test_export() {
+        let mut profile = load("profiler-wall.jfr");
+        profile
+            .apply_filter(Filter {
+                thread_name_regex: Some("GC Thread".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let json: Value = serde_json::from_str(
+            export(&profile, "profiler-wall.jfr", &Default::default())
+                .unwrap()
+                .as_str(),
+        )
+        .unwrap();
+        let frame_count = json["shared"]["frames"].as_array().unwrap().len();
+        let profiles = json["profiles"].as_array().unwrap();
+        assert_eq!(profiles.len(), 4);
+
+        let mut sample_count = 0;
+        for p in profiles {
+            assert!(p["name"].as_str().unwrap().starts_with("GC Thread"));
+            assert_eq!(p["type"], "sampled");
+            let samples = p["samples"].as_array().unwrap();
+            let weights = p["weights"].as_array().unwrap();
+            assert_eq!(samples.len(), weights.len());
+            assert!(weights.iter().all(|w| w.as_i64().unwrap() >= 0));
+            assert_eq!(
+                p["endValue"].as_i64().unwrap() - p["startValue"].as_i64().unwrap(),
+                weights.iter().map(|w| w.as_i64().unwrap()).sum::<i64>()
+            );
+            for stack in samples {
+                assert!(stack
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .all(|i| (i.as_u64().unwrap() as usize) < frame_count));
+            }
+            sample_count += samples.len();
+        }
+        assert_eq!(
+            sample_count as u64,
+            FlameGraph::from_execution_sample(&profile, &Default::default())
+                .root
+                .total_count
+        );
+    }
+
+    #[test]
+    fn test_export_aggregation() {
+        let profile = load("profiler-wall.jfr");
+        let config = AggregationConfig {
+            signature: true,
+            ..Default::default()
+        };
+
+        let json: Value = serde_json::from_str(
+            export(&profile, "profiler-wall.jfr", &config)
+                .unwrap()
+                .as_str(),
+        )
+        .unwrap();
+        let flame = FlameGraph::from_execution_sample(&profile, &config).to_folded();
+        let names = json["shared"]["frames"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| f["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        // frames are named in the same way as the flame graph
+        assert!(names.iter().any(|n| n.contains('(')));
+        assert!(names.iter().all(|n| flame.contains(n)));
+    }
+
+    #[test]
+    fn test_export_granularity() {
+        let profile = load("profiler-wall.jfr");
+        let config = AggregationConfig {
+            granularity: Granularity::Class,
+            ..Default::default()
+        };
+
+        let json: Value = serde_json::from_str(
+            export(&profile, "profiler-wall.jfr", &config)
+                .unwrap()
+                .as_str(),
+        )
+        .unwrap();
+        let flame = FlameGraph::from_execution_sample(&profile, &config);
+        let folded = flame.to_folded();
+        let names = json["shared"]["frames"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| f["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert!(names.contains(&"java/lang/Thread"));
+        assert!(names.iter().all(|n| folded.contains(n)));
+
+        // consecutive frames in a class are merged like the flame graph
+        let mut max_depth = 0;
+        for p in json["profiles"].as_array().unwrap() {
+            for stack in p["samples"].as_array().unwrap() {
+                let stack = stack.as_array().unwrap();
+                assert!(stack.windows(2).all(|w| w[0] != w[1]));
+                max_depth = max_depth.max(stack.len());
+            }
+        }
+        assert_eq!(max_depth, flame.depth);
+    }
+}
@@ -0,0 +1,22 @@
+//! Helpers shared by tests.
+
+use crate::execution_sample::Profile;
+use std::fs;
+use std::path::PathBuf;
+
+/// Read the file in test-data
+pub fn read(name: &str) -> Vec<u8> {
+    fs::read(
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("test-data")
+            .join(name),
+    )
+    .unwrap()
+}
+
+/// Load the recording in test-data as an execution sample profile
+pub fn load(name: &str) -> Profile {
+    let mut profile = Profile::default();
+    profile.load(read(name)).unwrap();
+    profile
+}
@@ -0,0 +1,276 @@
+//! Conversion between timestamps and wall-clock times, so that timestamps can be displayed
+//! in the time zone of the server rather than the browser.
+//! Should not contain any wasm dependencies.
+
+use anyhow::{anyhow, Result};
+use chrono::{FixedOffset, Local, NaiveDateTime, TimeZone};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+#[cfg(target_arch = "wasm32")]
+use tsify::Tsify;
+
+const NANOS_PER_SECOND: i64 = 1_000_000_000;
+
+/// The time zone to interpret or display wall-clock times in
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(target_arch = "wasm32", derive(Tsify))]
+#[cfg_attr(target_arch = "wasm32", tsify(into_wasm_abi, from_wasm_abi))]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum TimeZoneConfig {
+    /// The time zone of the browser
+    #[default]
+    Local,
+    Utc,
+    /// Offset east of UTC
+    #[serde(rename_all = "camelCase")]
+    FixedOffset {
+        offset_seconds: i32,
+    },
+    /// IANA time zone name, e.g. `Asia/Tokyo`
+    Iana {
+        name: String,
+    },
+}
+
+/// How to display timestamps
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[cfg_attr(target_arch = "wasm32", derive(Tsify))]
+#[cfg_attr(target_arch = "wasm32", tsify(into_wasm_abi, from_wasm_abi))]
+#[serde(rename_all = "camelCase")]
+pub struct TimeDisplayConfig {
+    /// Display the time elapsed since the start of the recording instead of the wall-clock time
+    pub elapsed: bool,
+    pub time_zone: TimeZoneConfig,
+}
+
+/// Time zone resolved from the config
+#[derive(Copy, Clone, Debug)]
+pub enum Zone {
+    Local,
+    Fixed(FixedOffset),
+    Iana(Tz),
+}
+
+impl TimeZoneConfig {
+    pub fn resolve(&self) -> Result<Zone> {
+        match self {
+            TimeZoneConfig::Local => Ok(Zone::Local),
+            TimeZoneConfig::Utc => Ok(Zone::Fixed(FixedOffset::east_opt(0).unwrap())),
+            TimeZoneConfig::FixedOffset { offset_seconds } => {
+                FixedOffset::east_opt(*offset_seconds)
+                    .map(Zone::Fixed)
+                    .ok_or_else(|| anyhow!("Invalid offset: {} seconds", offset_seconds))
+            }
+            TimeZoneConfig::Iana { name } => name
+                .parse::<Tz>()
+                .map(Zone::Iana)
+                .map_err(|e| anyhow!("Invalid time zone: {}", e)),
+        }
+    }
+}
+
+impl Zone {
+    /// Interpret the wall-clock time in the zone.
+    /// The earlier one is taken if the time is ambiguous, e.g. at the end of DST.
+    pub fn to_timestamp_millis(&self, datetime: &NaiveDateTime) -> Option<i64> {
+        match self {
+            Zone::Local => Self::local_to_millis(&Local, datetime),
+            Zone::Fixed(offset) => Self::local_to_millis(offset, datetime),
+            Zone::Iana(tz) => Self::local_to_millis(tz, datetime),
+        }
+    }
+
+    /// Format the timestamp as `%Y-%m-%d %H:%M:%S` in the zone, excluding the fraction
+    fn format(&self, timestamp_nanos: i64) -> String {
+        match self {
+            Zone::Local => Self::format_in(&Local, timestamp_nanos),
+            Zone::Fixed(offset) => Self::format_in(offset, timestamp_nanos),
+            Zone::Iana(tz) => Self::format_in(tz, timestamp_nanos),
+        }
+    }
+
+    fn local_to_millis<Z: TimeZone>(zone: &Z, datetime: &NaiveDateTime) -> Option<i64> {
+        zone.from_local_datetime(datetime)
+            .earliest()
+            .map(|t| t.timestamp_millis())
+    }
+
+    fn format_in<Z: TimeZone>(zone: &Z, timestamp_nanos: i64) -> String
+    where
+        Z::Offset: Display,
+    {
+        zone.timestamp_nanos(timestamp_nanos)
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string()
+    }
+}
+
+/// Formats timestamps according to the TimeDisplayConfig
+#[derive(Clone, Debug)]
+pub struct TimeFormatter {
+    zone: Zone,
+    elapsed: bool,
+}
+
+impl Default for TimeFormatter {
+    fn default() -> Self {
+        Self {
+            zone: Zone::Local,
+            elapsed: false,
+        }
+    }
+}
+
+impl TimeFormatter {
+    pub fn new(config: &TimeDisplayConfig) -> Result<Self> {
+        Ok(Self {
+            zone: config.time_zone.resolve()?,
+            elapsed: config.elapsed,
+        })
+    }
+
+    /// Format the timestamp with the fraction of the given digits (up to 9).
+    /// `start_nanos` is the origin of the elapsed time.
+    pub fn format(&self, timestamp_nanos: i64, start_nanos: i64, fraction_digits: u32) -> String {
+        let fraction_digits = fraction_digits.min(9);
+        let fraction = |nanos: i64| {
+            if fraction_digits == 0 {
+                String::new()
+            } else {
+                format!(
+                    ".{:0width$}",
+                    nanos / 10_i64.pow(9 - fraction_digits),
+                    width = fraction_digits as usize
+                )
+            }
+        };
+
+        if self.elapsed {
+            let elapsed = timestamp_nanos - start_nanos;
+            let sign = if elapsed < 0 { "-" } else { "+" };
+            let elapsed = elapsed.abs();
+            let seconds = elapsed / NANOS_PER_SECOND;
+            format!(
+                "{}{:02}:{:02}:{:02}{}",
+                sign,
+                seconds / 3600,
+                seconds / 60 % 60,
+                seconds % 60,
+                fraction(elapsed % NANOS_PER_SECOND)
+            )
+        } else {
+            format!(
+                "{}{}",
+                self.zone.format(timestamp_nanos),
+                fraction(timestamp_nanos.rem_euclid(NANOS_PER_SECOND))
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::time::{TimeDisplayConfig, TimeFormatter, TimeZoneConfig};
+    use chrono::NaiveDateTime;
+
+    // 2022-09-23T05:47:15.693456789Z
+    const TIMESTAMP_NANOS: i64 = 1_663_912_035_693_456_789;
+
+    fn formatter(time_zone: TimeZoneConfig, elapsed: bool) -> TimeFormatter {
+        TimeFormatter::new(&TimeDisplayConfig { elapsed, time_zone }).unwrap()
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(
+            formatter(TimeZoneConfig::Utc, false).format(TIMESTAMP_NANOS, 0, 9),
+            "2022-09-23 05:47:15.693456789"
+        );
+        assert_eq!(
+            formatter(TimeZoneConfig::Utc, false).format(TIMESTAMP_NANOS, 0, 3),
+            "2022-09-23 05:47:15.693"
+        );
+        assert_eq!(
+            formatter(
+                TimeZoneConfig::FixedOffset {
+                    offset_seconds: 9 * 3600
+                },
+                false
+            )
+            .format(TIMESTAMP_NANOS, 0, 0),
+            "2022-09-23 14:47:15"
+        );
+        let iana = |name: &str| TimeZoneConfig::Iana {
+            name: name.to_string(),
+        };
+        assert_eq!(
+            formatter(iana("Asia/Tokyo"), false).format(TIMESTAMP_NANOS, 0, 3),
+            "2022-09-23 14:47:15.693"
+        );
+        // DST is in effect
+        assert_eq!(
+            formatter(iana("America/New_York"), false).format(TIMESTAMP_NANOS, 0, 3),
+            "2022-09-23 01:47:15.693"
+        );
+    }
+
+    #[test]
+    fn test_format_elapsed() {
+        let formatter = formatter(TimeZoneConfig::Utc, true);
+        let start = TIMESTAMP_NANOS - 3_723_000_000_123;
+        assert_eq!(
+            formatter.format(TIMESTAMP_NANOS, start, 9),
+            "+01:02:03.000000123"
+        );
+        assert_eq!(formatter.format(start, start, 3), "+00:00:00.000");
+        assert_eq!(
+            formatter.format(start - 1_500_000_000, start, 3),
+            "-00:00:01.500"
+        );
+    }
+
+    #[test]
+    fn test_resolve() {
+        let datetime =
+            NaiveDateTime::parse_from_str("2022-09-23 05:47:15", "%Y-%m-%d %H:%M:%S").unwrap();
+        let utc = TimeZoneConfig::Utc
+            .resolve()
+            .unwrap()
+            .to_timestamp_millis(&datetime)
+            .unwrap();
+        assert_eq!(utc, 1_663_912_035_000);
+        let jst = TimeZoneConfig::Iana {
+            name: "Asia/Tokyo".to_string(),
+        }
+        .resolve()
+        .unwrap()
+        .to_timestamp_millis(&datetime)
+        .unwrap();
+        assert_eq!(utc - jst, 9 * 3600 * 1000);
+
+        // 01:30 occurs twice at the end of DST
+        let datetime =
+            NaiveDateTime::parse_from_str("2022-11-06 01:30:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let new_york = TimeZoneConfig::Iana {
+            name: "America/New_York".to_string(),
+        }
+        .resolve()
+        .unwrap();
+        assert_eq!(
+            new_york.to_timestamp_millis(&datetime).unwrap(),
+            1_667_712_600_000
+        );
+
+        assert!(TimeZoneConfig::Iana {
+            name: "Mars/Olympus_Mons".to_string()
+        }
+        .resolve()
+        .is_err());
+        assert!(TimeZoneConfig::FixedOffset {
+            offset_seconds: 86400
+        }
+        .resolve()
+        .is_err());
+    }
+}
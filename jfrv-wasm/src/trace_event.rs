@@ -0,0 +1,355 @@
+//! Conversion to Chrome's Trace Event Format, which can be opened in Perfetto or chrome://tracing.
+//! See https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU
+//! Should not contain any wasm dependencies.
+
+use crate::flame_graph::{AggregationConfig, Aggregator};
+use crate::profile::{ConstantPoolKey, ExecutionSample, StackTrace, Thread};
+use crate::{execution_sample, jbm};
+use anyhow::Result;
+use rustc_hash::FxHashMap;
+use serde::Serialize;
+
+/// Trace events require a process id though jfrv doesn't have one
+const PROCESS_ID: i64 = 1;
+const CATEGORY_EXECUTION_SAMPLE: &str = "ExecutionSample";
+const CATEGORY_OFF_CPU: &str = "OffCpu";
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Trace {
+    trace_events: Vec<TraceEvent>,
+    display_time_unit: &'static str,
+    other_data: OtherData,
+}
+
+/// Metadata of the trace which is shown by the viewers as is
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OtherData {
+    /// Epoch nanoseconds which the timestamps of events are relative to
+    start_nanos: i64,
+}
+
+#[derive(Serialize)]
+struct TraceEvent {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cat: Option<&'static str>,
+    ph: &'static str,
+    /// In microseconds since the start of the profile.
+    /// Epoch microseconds don't fit in f64 precisely
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ts: Option<f64>,
+    /// In microseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dur: Option<f64>,
+    pid: i64,
+    tid: i64,
+    args: Args,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Args {
+    Name {
+        name: String,
+    },
+    SortIndex {
+        sort_index: usize,
+    },
+    Stack {
+        /// Frames from the top of the stack
+        stack: Vec<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        samples: Option<usize>,
+    },
+}
+
+impl TraceEvent {
+    fn complete(
+        name: &str,
+        category: &'static str,
+        thread: &Thread,
+        origin_nanos: i64,
+        start_nanos: i64,
+        duration_nanos: i64,
+        args: Args,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            cat: Some(category),
+            ph: "X",
+            ts: Some((start_nanos - origin_nanos) as f64 / 1000.0),
+            dur: Some(duration_nanos as f64 / 1000.0),
+            pid: PROCESS_ID,
+            tid: thread.id,
+            args,
+        }
+    }
+}
+
+/// Export the execution samples passing the current filter as trace events.
+/// Each thread becomes a track, and consecutive samples with identical stack
+/// are merged into a complete event which lasts until the next different sample.
+/// Frames are named and merged in the same way as the flame graph with the config.
+pub fn export_execution_sample(
+    profile: &execution_sample::Profile,
+    config: &AggregationConfig,
+) -> Result<String> {
+    let aggregator = Aggregator::new(config);
+    // stack trace keys may differ across chunks even for the same trace,
+    // so runs are detected by the frame names
+    let mut stacks: FxHashMap<ConstantPoolKey, Vec<String>> = FxHashMap::default();
+    let mut events = metadata_events(profile.filtered_threads());
+
+    for thread in profile.filtered_threads() {
+        let samples: Vec<&ExecutionSample> = profile
+            .per_thread_samples
+            .get(&thread.id)
+            .map(|samples| {
+                samples
+                    .iter()
+                    .filter(|s| profile.is_valid_sample(thread, *s))
+                    .filter(|s| profile.stack_trace_pool.contains_key(&s.stack_trace_key))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        for sample in samples.iter() {
+            stacks.entry(sample.stack_trace_key).or_insert_with(|| {
+                aggregator
+                    .aggregate(&profile.stack_trace_pool[&sample.stack_trace_key])
+                    .into_iter()
+                    .rev()
+                    .map(|(_, id)| id.name)
+                    .collect()
+            });
+        }
+
+        let mut i = 0;
+        while i < samples.len() {
+            let stack = &stacks[&samples[i].stack_trace_key];
+            let mut j = i + 1;
+            while j < samples.len()
+                && (samples[j].stack_trace_key == samples[i].stack_trace_key
+                    || &stacks[&samples[j].stack_trace_key] == stack)
+            {
+                j += 1;
+            }
+
+            let start = samples[i].timestamp_nanos;
+            let end = match samples.get(j) {
+                Some(next) => next.timestamp_nanos,
+                // the last run lasts as long as the sampling interval just before
+                None if j >= 2 => {
+                    samples[j - 1].timestamp_nanos * 2 - samples[j - 2].timestamp_nanos
+                }
+                None => samples[j - 1].timestamp_nanos,
+            };
+            events.push(TraceEvent::complete(
+                stack.first().map(|s| s.as_str()).unwrap_or(""),
+                CATEGORY_EXECUTION_SAMPLE,
+                thread,
+                profile.interval.start_nanos,
+                start,
+                end - start,
+                Args::Stack {
+                    stack: stack.clone(),
+                    samples: Some(j - i),
+                },
+            ));
+            i = j;
+        }
+    }
+
+    to_json(events, profile.interval.start_nanos)
+}
+
+/// Export the off-cpu samples of the filtered threads as trace events.
+/// Each thread becomes a track, and each off-cpu interval becomes a complete event.
+pub fn export_jbm(profile: &jbm::Profile) -> Result<String> {
+    let mut events = metadata_events(profile.filtered_threads());
+
+    for thread in profile.filtered_threads() {
+        if let Some(samples) = profile.per_thread_samples.get(&thread.id) {
+            for sample in samples.iter().filter(|s| profile.is_valid_sample(s)) {
+                if let Some(trace) = profile.stack_trace_pool.get(&sample.stack_trace_key) {
+                    events.push(TraceEvent::complete(
+                        CATEGORY_OFF_CPU,
+                        CATEGORY_OFF_CPU,
+                        thread,
+                        profile.interval.start_nanos,
+                        sample.timestamp_nanos,
+                        sample.duration_nanos,
+                        Args::Stack {
+                            stack: frame_names(trace),
+                            samples: None,
+                        },
+                    ));
+                }
+            }
+        }
+    }
+
+    to_json(events, profile.interval.start_nanos)
+}
+
+/// Name the tracks by the thread name and keep the order of jfrv's thread list
+fn metadata_events(threads: &[Thread]) -> Vec<TraceEvent> {
+    let mut events = vec![TraceEvent {
+        name: "process_name".to_string(),
+        cat: None,
+        ph: "M",
+        ts: None,
+        dur: None,
+        pid: PROCESS_ID,
+        tid: 0,
+        args: Args::Name {
+            name: "jfrv".to_string(),
+        },
+    }];
+    for (i, thread) in threads.iter().enumerate() {
+        events.push(TraceEvent {
+            name: "thread_name".to_string(),
+            cat: None,
+            ph: "M",
+            ts: None,
+            dur: None,
+            pid: PROCESS_ID,
+            tid: thread.id,
+            args: Args::Name {
+                name: thread.name.clone(),
+            },
+        });
+        events.push(TraceEvent {
+            name: "thread_sort_index".to_string(),
+            cat: None,
+            ph: "M",
+            ts: None,
+            dur: None,
+            pid: PROCESS_ID,
+            tid: thread.id,
+            args: Args::SortIndex { sort_index: i },
+        });
+    }
+    events
+}
+
+fn frame_names(trace: &StackTrace) -> Vec<String> {
+    trace.frames.iter().map(|f| f.name().to_string()).collect()
+}
+
+fn to_json(events: Vec<TraceEvent>, start_nanos: i64) -> Result<String> {
+    Ok(serde_json::to_string(&Trace {
+        trace_events: events,
+        display_time_unit: "ms",
+        other_data: OtherData { start_nanos },
+    })?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::flame_graph::{AggregationConfig, FlameGraph};
+    use crate::jbm;
+    use crate::test_util::{load, read};
+    use crate::trace_event::{export_execution_sample, export_jbm};
+    use serde_json::Value;
+
+    #[test]
+    fn test_export_execution_sample() {
+        let profile = load("profiler-wall.jfr");
+
+        let json: Value = serde_json::from_str(
+            export_execution_sample(&profile, &Default::default())
+                .unwrap()
+                .as_str(),
+        )
+        .unwrap();
+        let events = json["traceEvents"].as_array().unwrap();
+        let threads = events
+            .iter()
+            .filter(|e| e["ph"] == "M" && e["name"] == "thread_name")
+            .count();
+        assert_eq!(threads, profile.filtered_threads().len());
+
+        let complete: Vec<_> = events.iter().filter(|e| e["ph"] == "X").collect();
+        let samples: u64 = complete
+            .iter()
+            .map(|e| e["args"]["samples"].as_u64().unwrap())
+            .sum();
+        assert_eq!(
+            samples,
+            FlameGraph::from_execution_sample(&profile, &Default::default())
+                .root
+                .total_count
+        );
+        // consecutive samples are merged
+        assert!((complete.len() as u64) < samples);
+        assert!(complete.iter().all(|e| e["dur"].as_f64().unwrap() >= 0.0));
+        assert_eq!(
+            json["otherData"]["startNanos"].as_i64().unwrap(),
+            profile.interval.start_nanos
+        );
+        assert!(complete.iter().all(|e| e["ts"].as_f64().unwrap() >= 0.0));
+
+        // events in a track don't overlap.
+        // relative microseconds are exact in nanoseconds
+        let nanos = |v: &Value| (v.as_f64().unwrap() * 1000.0).round() as i64;
+        for w in complete.windows(2) {
+            if w[0]["tid"] == w[1]["tid"] {
+                assert!(nanos(&w[0]["ts"]) + nanos(&w[0]["dur"]) <= nanos(&w[1]["ts"]));
+                assert_ne!(w[0]["args"]["stack"], w[1]["args"]["stack"]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_export_execution_sample_aggregation() {
+        let profile = load("profiler-wall.jfr");
+        let config = AggregationConfig {
+            signature: true,
+            ..Default::default()
+        };
+
+        let json: Value =
+            serde_json::from_str(export_execution_sample(&profile, &config).unwrap().as_str())
+                .unwrap();
+        let flame = FlameGraph::from_execution_sample(&profile, &config).to_folded();
+        let names = json["traceEvents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|e| e["ph"] == "X")
+            .flat_map(|e| e["args"]["stack"].as_array().unwrap().iter())
+            .map(|n| n.as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert!(names.iter().any(|n| n.contains('(')));
+        assert!(names.iter().all(|n| flame.contains(n)));
+    }
+
+    #[test]
+    fn test_export_jbm() {
+        let mut profile = jbm::Profile::default();
+        profile.load(read("jbm.log"), &Default::default()).unwrap();
+
+        let json: Value = serde_json::from_str(export_jbm(&profile).unwrap().as_str()).unwrap();
+        let events = json["traceEvents"].as_array().unwrap();
+        let spans: Vec<_> = events.iter().filter(|e| e["ph"] == "X").collect();
+        assert_eq!(
+            spans.len(),
+            profile
+                .per_thread_samples
+                .values()
+                .map(|s| s.len())
+                .sum::<usize>()
+        );
+        assert!(spans
+            .iter()
+            .all(|e| !e["args"]["stack"].as_array().unwrap().is_empty()));
+        assert_eq!(
+            json["otherData"]["startNanos"].as_i64().unwrap(),
+            profile.interval.start_nanos
+        );
+        assert!(spans.iter().all(|e| e["ts"].as_f64().unwrap() >= 0.0));
+    }
+}
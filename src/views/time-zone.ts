@@ -0,0 +1,33 @@
+import {TimeDisplayConfig, TimeZoneConfig} from "../../jfrv-wasm/pkg";
+
+export const ELAPSED = "elapsed"
+
+/**
+ * Parse the time zone input, which is one of "local", "UTC",
+ * a fixed offset like "+09:00", or an IANA zone name like "Asia/Tokyo".
+ */
+export function parseTimeZone(text: string | undefined): TimeZoneConfig {
+  const value = text?.trim() ?? ""
+  if (value.length === 0 || value.toLowerCase() === "local") {
+    return { type: "local" }
+  }
+  if (value.toUpperCase() === "UTC" || value.toUpperCase() === "Z") {
+    return { type: "utc" }
+  }
+  const offset = value.match(/^([+-])(\d{1,2})(?::?(\d{2}))?$/)
+  if (offset) {
+    const seconds = parseInt(offset[2]) * 3600 + parseInt(offset[3] ?? "0") * 60
+    return { type: "fixedOffset", offsetSeconds: offset[1] === "-" ? -seconds : seconds }
+  }
+  return { type: "iana", name: value }
+}
+
+/**
+ * Parse the time display input, which is either "elapsed" or a time zone accepted by parseTimeZone
+ */
+export function parseTimeDisplay(text: string | undefined): TimeDisplayConfig {
+  if (text?.trim().toLowerCase() === ELAPSED) {
+    return { elapsed: true, timeZone: { type: "local" } }
+  }
+  return { elapsed: false, timeZone: parseTimeZone(text) }
+}